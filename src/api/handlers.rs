@@ -1,21 +1,71 @@
-use amqprs::{channel::BasicPublishArguments, BasicProperties};
-use axum::{extract::State, http::StatusCode, Json};
-use tracing::{info, warn};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use tracing::{error, info, warn};
 use validator::Validate;
 
 use crate::{
     api::errors::HttpError,
-    domain::notification::{EmailNotification, Notification},
-    infra::amqp::AmqpPublisher,
+    domain::notification::{
+        EmailNotification, Notification, NotificationChannel, NotificationStatus, PushNotification,
+        SMSNotification,
+    },
+    idempotency::store::{IdempotencyStore, Reservation},
+    infra::{
+        amqp::PublishError,
+        connection::ConnectionManager,
+        organizations::{OrganizationError, OrganizationRegistry},
+    },
+    providers::email::{EmailSender, TestRecipients},
+    status::store::{NotificationStatusStore, StatusUpdate},
+    templates::email::{
+        cache::EmailTemplateCache,
+        engine::RenderedEmail,
+        locale::normalize_locale,
+        repository::WritableEmailTemplateRepository,
+        template::{EmailTemplate, TemplateError, TemplateKey},
+        versions::store::VersionError,
+    },
 };
 
 use super::{
-    models::{CreateEmailNotificationRequest, CreateNotificationResponse},
-    routes::HttpResponse,
+    models::{
+        CreateEmailNotificationRequest, CreateEmailTemplateRequest, CreateNotificationResponse,
+        CreateOrganizationRequest, CreatePushNotificationRequest, CreateSmsNotificationRequest,
+        EmailTemplateRequest, EmailTemplateResponse, EmailTemplateSummary, HealthcheckResponse,
+        ListEmailTemplatesResponse, ListOrganizationsResponse, ListTemplateVersionsResponse,
+        NotificationStatusResponse, OrganizationResponse, PreviewTemplateRequest,
+        RenderedEmailResponse, TemplateLocaleQuery, TemplateVersionResponse,
+        TemplateVersionSummary, TestSendResponse, TestSendTemplateRequest,
+    },
+    routes::{AppState, HttpResponse},
 };
 
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Reports `degraded` while the broker connection, or any of its channels, is
+/// being recovered
+pub async fn healthcheck(
+    State(connection): State<Arc<ConnectionManager>>,
+) -> (StatusCode, HttpResponse<HealthcheckResponse>) {
+    if connection.is_healthy() {
+        (StatusCode::OK, Json(HealthcheckResponse { status: "ok" }))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(HealthcheckResponse { status: "degraded" }),
+        )
+    }
+}
+
 pub async fn create_email_notification(
-    State(publisher): State<AmqpPublisher>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<CreateEmailNotificationRequest>,
 ) -> Result<HttpResponse<CreateNotificationResponse>, HttpError> {
     payload.validate().map_err(|err| {
@@ -30,6 +80,20 @@ pub async fn create_email_notification(
         }
     })?;
 
+    let idempotency_key = idempotency_key(&headers)?;
+
+    ensure_organization_exists(&state.organizations, &payload.organization_id).await?;
+
+    let locale = payload.locale.as_deref().map(normalize_locale);
+
+    let template = validate_email_metadata(
+        &state.email_templates,
+        &payload.organization_id,
+        &payload.template_id,
+        locale.as_deref(),
+        &payload.metadata,
+    )?;
+
     info!(
         "Received email notification request for organization: {}",
         payload.organization_id
@@ -37,11 +101,140 @@ pub async fn create_email_notification(
 
     info!("Email notification payload: {:?}", payload);
 
+    let template_version = state.email_templates.published_version(&template);
+
+    let notification = EmailNotification::new(
+        template.id,
+        template.organization_id,
+        template_version,
+        locale,
+        payload.recipient,
+        payload.metadata,
+    );
+
+    let id = publish_notification(
+        &state,
+        &payload.organization_id,
+        NotificationChannel::Email,
+        &notification.id,
+        &notification,
+        idempotency_key.as_deref(),
+    )
+    .await?;
+
+    Ok(Json(CreateNotificationResponse { id }))
+}
+
+pub async fn create_sms_notification(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateSmsNotificationRequest>,
+) -> Result<HttpResponse<CreateNotificationResponse>, HttpError> {
+    payload.validate().map_err(|err| {
+        warn!(
+            "Invalid SMS notification request payload: {:?}, error: {:?}",
+            payload, err
+        );
+
+        HttpError {
+            status_code: StatusCode::BAD_REQUEST,
+            message: format!("Invalid payload: {}", err),
+        }
+    })?;
+
+    let idempotency_key = idempotency_key(&headers)?;
+
+    ensure_channel_enabled(&state.organizations, NotificationChannel::Sms)?;
+
+    ensure_organization_exists(&state.organizations, &payload.organization_id).await?;
+
+    info!(
+        "Received SMS notification request for organization: {}",
+        payload.organization_id
+    );
+
     let notification =
-        EmailNotification::new(payload.template_id, payload.recipient, payload.metadata);
+        SMSNotification::new(payload.template_id, payload.phone_number, payload.metadata);
 
+    let id = publish_notification(
+        &state,
+        &payload.organization_id,
+        NotificationChannel::Sms,
+        &notification.id,
+        &notification,
+        idempotency_key.as_deref(),
+    )
+    .await?;
+
+    Ok(Json(CreateNotificationResponse { id }))
+}
+
+pub async fn create_push_notification(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreatePushNotificationRequest>,
+) -> Result<HttpResponse<CreateNotificationResponse>, HttpError> {
+    payload.validate().map_err(|err| {
+        warn!(
+            "Invalid push notification request payload: {:?}, error: {:?}",
+            payload, err
+        );
+
+        HttpError {
+            status_code: StatusCode::BAD_REQUEST,
+            message: format!("Invalid payload: {}", err),
+        }
+    })?;
+
+    let idempotency_key = idempotency_key(&headers)?;
+
+    ensure_channel_enabled(&state.organizations, NotificationChannel::Push)?;
+
+    ensure_organization_exists(&state.organizations, &payload.organization_id).await?;
+
+    info!(
+        "Received push notification request for organization: {}",
+        payload.organization_id
+    );
+
+    let notification = PushNotification::new(
+        payload.device_token,
+        payload.module,
+        payload.title,
+        payload.description,
+    );
+
+    let id = publish_notification(
+        &state,
+        &payload.organization_id,
+        NotificationChannel::Push,
+        &notification.id,
+        &notification,
+        idempotency_key.as_deref(),
+    )
+    .await?;
+
+    Ok(Json(CreateNotificationResponse { id }))
+}
+
+/// Claims the idempotency key, records the notification and publishes it to
+/// the organization's queue of the channel, waiting for the broker to confirm
+/// it. Returns the ID of the notification, which is the one created by an
+/// earlier request when the idempotency key is replayed.
+async fn publish_notification(
+    state: &AppState,
+    org_id: &str,
+    channel: NotificationChannel,
+    id: &str,
+    notification: &impl Notification,
+    idempotency_key: Option<&str>,
+) -> Result<String, HttpError> {
     let json_content = notification.to_json_string().map_err(|err| {
-        warn!("Failed to serialize email notification: {:?}", err);
+        warn!(
+            "Failed to serialize {} notification: {:?}",
+            channel.as_str(),
+            err
+        );
 
         HttpError {
             status_code: StatusCode::INTERNAL_SERVER_ERROR,
@@ -49,20 +242,651 @@ pub async fn create_email_notification(
         }
     })?;
 
-    let routing_key = format!("{}.email", payload.organization_id);
+    if let Some(key) = idempotency_key {
+        if let Some(existing) =
+            claim_idempotency_key(state.idempotency.as_ref(), org_id, key, id, channel).await?
+        {
+            info!(
+                "Replaying {} notification {} for idempotency key {}",
+                channel.as_str(),
+                existing,
+                key
+            );
+
+            return Ok(existing);
+        }
+    }
 
-    let publish_args = BasicPublishArguments::new(&publisher.exchange, &routing_key);
+    if let Err(err) = state.statuses.create(id, org_id, channel).await {
+        warn!(
+            "Failed to record {} notification status: {:?}",
+            channel.as_str(),
+            err
+        );
+
+        release_idempotency_key(state.idempotency.as_ref(), org_id, idempotency_key, id).await;
+
+        return Err(HttpError {
+            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: "Internal server error".to_string(),
+        });
+    }
+
+    let routing_key = format!("{}.{}", org_id, channel.as_str());
+
+    let publish_result = state
+        .publisher
+        .publish(&routing_key, id, json_content.into_bytes())
+        .await;
+
+    if let Err(err) = publish_result {
+        warn!(
+            "Failed to publish {} notification: {:?}",
+            channel.as_str(),
+            err
+        );
 
-    publisher
-        .channel
-        .basic_publish(
-            BasicProperties::default(),
-            json_content.into_bytes(),
-            publish_args,
+        record_status(
+            state.statuses.as_ref(),
+            id,
+            NotificationStatus::Failed,
+            StatusUpdate::failed(err.to_string()),
         )
+        .await;
+
+        release_idempotency_key(state.idempotency.as_ref(), org_id, idempotency_key, id).await;
+
+        return Err(publish_http_error(err));
+    }
+
+    record_status(
+        state.statuses.as_ref(),
+        id,
+        NotificationStatus::Published,
+        StatusUpdate::default(),
+    )
+    .await;
+
+    complete_idempotency_key(state.idempotency.as_ref(), org_id, idempotency_key, id).await;
+
+    info!(
+        "{} notification {} published successfully",
+        channel.as_str(),
+        id
+    );
+
+    Ok(id.to_string())
+}
+
+pub async fn get_notification(
+    State(statuses): State<Arc<dyn NotificationStatusStore>>,
+    Path((org_id, id)): Path<(String, String)>,
+) -> Result<HttpResponse<NotificationStatusResponse>, HttpError> {
+    let record = statuses.find_by_id(&id).await.map_err(|err| {
+        warn!("Failed to find notification status: {:?}", err);
+
+        HttpError {
+            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: "Internal server error".to_string(),
+        }
+    })?;
+
+    // Another organization's notification is reported as missing, so its
+    // IDs can't be probed
+    let record = record
+        .filter(|record| record.organization_id == org_id)
+        .ok_or_else(|| HttpError {
+            status_code: StatusCode::NOT_FOUND,
+            message: format!("Notification not found: {}", id),
+        })?;
+
+    Ok(Json(record.into()))
+}
+
+pub async fn list_organizations(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+) -> HttpResponse<ListOrganizationsResponse> {
+    Json(ListOrganizationsResponse {
+        organizations: organizations.list().await,
+    })
+}
+
+pub async fn register_organization(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    Json(payload): Json<CreateOrganizationRequest>,
+) -> Result<(StatusCode, HttpResponse<OrganizationResponse>), HttpError> {
+    payload.validate().map_err(|err| HttpError {
+        status_code: StatusCode::BAD_REQUEST,
+        message: format!("Invalid payload: {}", err),
+    })?;
+
+    let created = organizations
+        .register(&payload.id)
+        .await
+        .map_err(|err| match err {
+            OrganizationError::InvalidId(_) => HttpError {
+                status_code: StatusCode::BAD_REQUEST,
+                message: err.to_string(),
+            },
+            OrganizationError::ProvisioningError(..) | OrganizationError::StoreError(_) => {
+                warn!("Failed to register organization: {:?}", err);
+
+                HttpError {
+                    status_code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: "Internal server error".to_string(),
+                }
+            }
+        })?;
+
+    let status_code = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((status_code, Json(OrganizationResponse { id: payload.id })))
+}
+
+pub async fn list_template_versions(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path((org_id, id)): Path<(String, String)>,
+) -> Result<HttpResponse<ListTemplateVersionsResponse>, HttpError> {
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let key = TemplateKey::owned(&org_id, &id);
+
+    owned_template(repository.as_ref(), &key).await?;
+
+    let versions = email_templates
+        .list_versions(&key)
+        .await
+        .map_err(version_http_error)?;
+
+    if versions.is_empty() {
+        return Err(HttpError {
+            status_code: StatusCode::NOT_FOUND,
+            message: format!("Template not found: {}", id),
+        });
+    }
+
+    Ok(Json(ListTemplateVersionsResponse {
+        template_id: id,
+        versions: versions
+            .into_iter()
+            .map(|version| TemplateVersionSummary {
+                version: version.version,
+                created_at: version.created_at,
+                published: version.published,
+            })
+            .collect(),
+    }))
+}
+
+pub async fn get_template_version(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path((org_id, id, version)): Path<(String, String, i64)>,
+) -> Result<HttpResponse<TemplateVersionResponse>, HttpError> {
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let key = TemplateKey::owned(&org_id, &id);
+
+    owned_template(repository.as_ref(), &key).await?;
+
+    let version = email_templates
+        .find_version(&key, version)
+        .await
+        .map_err(version_http_error)?;
+
+    Ok(Json(version.into()))
+}
+
+/// Makes the version the one new notifications of the template render.
+/// Notifications already created keep their version.
+pub async fn publish_template_version(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path((org_id, id, version)): Path<(String, String, i64)>,
+) -> Result<HttpResponse<TemplateVersionResponse>, HttpError> {
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let key = TemplateKey::owned(&org_id, &id);
+
+    owned_template(repository.as_ref(), &key).await?;
+
+    let version = email_templates
+        .publish(&key, version)
+        .await
+        .map_err(version_http_error)?;
+
+    info!("Published version {} of template {}", version.version, id);
+
+    Ok(Json(version.into()))
+}
+
+pub async fn rollback_template(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path((org_id, id)): Path<(String, String)>,
+) -> Result<HttpResponse<TemplateVersionResponse>, HttpError> {
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let key = TemplateKey::owned(&org_id, &id);
+
+    owned_template(repository.as_ref(), &key).await?;
+
+    let version = email_templates
+        .rollback(&key)
+        .await
+        .map_err(version_http_error)?;
+
+    info!("Rolled template {} back to version {}", id, version.version);
+
+    Ok(Json(version.into()))
+}
+
+/// Renders a template the organization can send with sample metadata,
+/// without sending anything
+pub async fn preview_template(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    Path((org_id, id)): Path<(String, String)>,
+    Json(payload): Json<PreviewTemplateRequest>,
+) -> Result<HttpResponse<RenderedEmailResponse>, HttpError> {
+    payload.validate().map_err(|err| HttpError {
+        status_code: StatusCode::BAD_REQUEST,
+        message: format!("Invalid payload: {}", err),
+    })?;
+
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let key = available_template(&email_templates, &org_id, &id)?;
+
+    let rendered = render_preview(&email_templates, &key, &payload).await?;
+
+    Ok(Json(rendered.into()))
+}
+
+/// Renders a template and sends it straight to an allow-listed address,
+/// skipping the queue, the retries and the status tracking
+pub async fn test_send_template(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(email_sender): State<Arc<EmailSender>>,
+    State(test_recipients): State<Arc<TestRecipients>>,
+    Path((org_id, id)): Path<(String, String)>,
+    Json(payload): Json<TestSendTemplateRequest>,
+) -> Result<HttpResponse<TestSendResponse>, HttpError> {
+    payload.validate().map_err(|err| HttpError {
+        status_code: StatusCode::BAD_REQUEST,
+        message: format!("Invalid payload: {}", err),
+    })?;
+
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let key = available_template(&email_templates, &org_id, &id)?;
+
+    if !test_recipients.allows(&payload.recipient) {
+        warn!(
+            "Rejected test send of template {} to {}: recipient is not allow-listed",
+            id, payload.recipient
+        );
+
+        return Err(HttpError {
+            status_code: StatusCode::FORBIDDEN,
+            message: format!("Recipient is not allowed: {}", payload.recipient),
+        });
+    }
+
+    let rendered = render_preview(&email_templates, &key, &payload.preview).await?;
+
+    let provider_message_id = email_sender
+        .send(&payload.recipient, &rendered)
+        .await
+        .map_err(|err| {
+            error!("Failed to test send template {}: {:?}", key, err);
+
+            HttpError {
+                status_code: StatusCode::BAD_GATEWAY,
+                message: format!("Failed to send email: {}", err),
+            }
+        })?;
+
+    info!(
+        "Test sent template {} to {} with provider message id {}",
+        key, payload.recipient, provider_message_id
+    );
+
+    Ok(Json(TestSendResponse {
+        provider_message_id,
+    }))
+}
+
+/// The template the organization sends under the ID, its own or a shared one.
+/// Templates of other organizations are not found.
+fn available_template(
+    email_templates: &EmailTemplateCache,
+    org_id: &str,
+    id: &str,
+) -> Result<TemplateKey, HttpError> {
+    email_templates
+        .resolve(org_id, id)
+        .ok_or_else(|| HttpError {
+            status_code: StatusCode::NOT_FOUND,
+            message: format!("Template not found: {}", id),
+        })
+}
+
+async fn render_preview(
+    email_templates: &EmailTemplateCache,
+    key: &TemplateKey,
+    payload: &PreviewTemplateRequest,
+) -> Result<RenderedEmail, HttpError> {
+    let locale = payload.locale.as_deref().map(normalize_locale);
+
+    email_templates
+        .render(key, payload.version, locale.as_deref(), &payload.metadata)
+        .await
+        .map_err(template_http_error)
+}
+
+pub async fn list_email_templates(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path(org_id): Path<String>,
+) -> Result<HttpResponse<ListEmailTemplatesResponse>, HttpError> {
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let ids = repository
+        .list_organization_ids(&org_id)
+        .await
+        .map_err(template_http_error)?;
+    let mut templates = Vec::new();
+
+    for id in ids {
+        let key = TemplateKey::owned(&org_id, &id);
+
+        templates.push(EmailTemplateSummary {
+            locales: repository
+                .list_locales(&key)
+                .await
+                .map_err(template_http_error)?,
+            published_version: email_templates.published_version(&key),
+            id,
+        });
+    }
+
+    Ok(Json(ListEmailTemplatesResponse { templates }))
+}
+
+pub async fn get_email_template(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path((org_id, id)): Path<(String, String)>,
+    Query(query): Query<TemplateLocaleQuery>,
+) -> Result<HttpResponse<EmailTemplateResponse>, HttpError> {
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let key = TemplateKey::owned(&org_id, &id);
+
+    let template = owned_template(repository.as_ref(), &key).await?;
+
+    let template = match query.locale {
+        Some(locale) => repository
+            .find_localized(&key, &normalize_locale(&locale))
+            .await
+            .map_err(template_http_error)?,
+        None => template,
+    };
+
+    Ok(Json(EmailTemplateResponse {
+        template,
+        published_version: email_templates.published_version(&key),
+    }))
+}
+
+/// Creates a template, or a translation of one with `locale`. Templates are
+/// compiled before they are saved, and published right away.
+pub async fn create_email_template(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path(org_id): Path<String>,
+    Json(payload): Json<CreateEmailTemplateRequest>,
+) -> Result<(StatusCode, HttpResponse<EmailTemplateResponse>), HttpError> {
+    payload.validate().map_err(|err| HttpError {
+        status_code: StatusCode::BAD_REQUEST,
+        message: format!("Invalid payload: {}", err),
+    })?;
+
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let mut template = payload.template.into_template(payload.id, org_id.clone());
+    template.locale = template.locale.as_deref().map(normalize_locale);
+
+    let key = template.key();
+
+    // Other organizations can use the same IDs, so only the templates of
+    // the organization conflict
+    let existing = match &template.locale {
+        Some(locale) => {
+            owned_template(repository.as_ref(), &key).await?;
+            repository.find_localized(&key, locale).await
+        }
+        None => repository.find_by_id(&key).await,
+    };
+
+    match existing {
+        Err(TemplateError::NotFound(_)) => {}
+        Ok(_) | Err(TemplateError::InvalidTemplate { .. }) => {
+            return Err(HttpError {
+                status_code: StatusCode::CONFLICT,
+                message: format!("Template already exists: {}", template.id),
+            });
+        }
+        Err(err) => return Err(template_http_error(err)),
+    }
+
+    let response = save_email_template(&email_templates, repository.as_ref(), template).await?;
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+/// Replaces a template, or creates or replaces one of its translations
+pub async fn update_email_template(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path((org_id, id)): Path<(String, String)>,
+    Json(payload): Json<EmailTemplateRequest>,
+) -> Result<HttpResponse<EmailTemplateResponse>, HttpError> {
+    payload.validate().map_err(|err| HttpError {
+        status_code: StatusCode::BAD_REQUEST,
+        message: format!("Invalid payload: {}", err),
+    })?;
+
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    owned_template(repository.as_ref(), &TemplateKey::owned(&org_id, &id)).await?;
+
+    let mut template = payload.into_template(id, org_id);
+    template.locale = template.locale.as_deref().map(normalize_locale);
+
+    let response = save_email_template(&email_templates, repository.as_ref(), template).await?;
+
+    Ok(Json(response))
+}
+
+/// Deletes a template with its translations, or only the translation to
+/// `locale`. Its versions are kept for auditing.
+pub async fn delete_email_template(
+    State(organizations): State<Arc<OrganizationRegistry>>,
+    State(email_templates): State<Arc<EmailTemplateCache>>,
+    State(repository): State<Arc<dyn WritableEmailTemplateRepository>>,
+    Path((org_id, id)): Path<(String, String)>,
+    Query(query): Query<TemplateLocaleQuery>,
+) -> Result<StatusCode, HttpError> {
+    ensure_organization_exists(&organizations, &org_id).await?;
+
+    let key = TemplateKey::owned(&org_id, &id);
+
+    owned_template(repository.as_ref(), &key).await?;
+
+    let locale = query.locale.as_deref().map(normalize_locale);
+
+    repository
+        .delete(&key, locale.as_deref())
+        .await
+        .map_err(template_http_error)?;
+
+    info!("Deleted email template {} of organization {}", id, org_id);
+
+    // Deleting a translation changes the template, so it is published as a
+    // new version
+    if locale.is_some() {
+        email_templates
+            .record(&key)
+            .await
+            .map_err(version_http_error)?;
+    } else {
+        email_templates.remove(&key).await;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn ensure_channel_enabled(
+    organizations: &OrganizationRegistry,
+    channel: NotificationChannel,
+) -> Result<(), HttpError> {
+    if organizations.supports(channel) {
+        return Ok(());
+    }
+
+    warn!(
+        "Rejected {} notification: the channel is not configured",
+        channel.as_str()
+    );
+
+    Err(HttpError {
+        status_code: StatusCode::SERVICE_UNAVAILABLE,
+        message: format!("{} notifications are not configured", channel.as_str()),
+    })
+}
+
+async fn ensure_organization_exists(
+    organizations: &OrganizationRegistry,
+    org_id: &str,
+) -> Result<(), HttpError> {
+    if organizations.contains(org_id).await {
+        return Ok(());
+    }
+
+    warn!("Rejected notification for unknown organization: {}", org_id);
+
+    Err(HttpError {
+        status_code: StatusCode::UNPROCESSABLE_ENTITY,
+        message: format!("Unknown organization: {}", org_id),
+    })
+}
+
+/// Resolves the template the organization sends under the ID, rejecting
+/// metadata missing variables of the template, or with values of the wrong
+/// type, before the notification is published
+fn validate_email_metadata(
+    email_templates: &EmailTemplateCache,
+    organization_id: &str,
+    template_id: &str,
+    locale: Option<&str>,
+    metadata: &serde_json::Value,
+) -> Result<TemplateKey, HttpError> {
+    let unknown_template = || HttpError {
+        status_code: StatusCode::UNPROCESSABLE_ENTITY,
+        message: format!("Unknown template: {}", template_id),
+    };
+
+    // Templates of other organizations are never resolved, so they are
+    // reported as unknown
+    let key = email_templates
+        .resolve(organization_id, template_id)
+        .ok_or_else(|| {
+            warn!(
+                "Rejected email notification: unknown template {} for organization {}",
+                template_id, organization_id
+            );
+
+            unknown_template()
+        })?;
+
+    let errors = email_templates
+        .validate_metadata(&key, locale, metadata)
+        .map_err(|err| {
+            warn!("Rejected email notification: {}", err);
+
+            unknown_template()
+        })?;
+
+    if errors.is_empty() {
+        return Ok(key);
+    }
+
+    let fields: Vec<String> = errors.iter().map(ToString::to_string).collect();
+
+    warn!(
+        "Invalid metadata for template {}: {:?}",
+        template_id, fields
+    );
+
+    Err(HttpError {
+        status_code: StatusCode::BAD_REQUEST,
+        message: format!("Invalid metadata: {}", fields.join(", ")),
+    })
+}
+
+/// Reads the `Idempotency-Key` header, which clients set to safely retry a
+/// notification request
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, HttpError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+
+    let key = value
+        .to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH)
+        .ok_or_else(|| HttpError {
+            status_code: StatusCode::BAD_REQUEST,
+            message: format!(
+                "Invalid {} header: must be 1 to {} visible ASCII characters",
+                IDEMPOTENCY_KEY_HEADER, MAX_IDEMPOTENCY_KEY_LENGTH
+            ),
+        })?;
+
+    Ok(Some(key.to_string()))
+}
+
+/// Claims the idempotency key for the notification. Returns the ID of the
+/// notification created by an earlier request with the same key.
+async fn claim_idempotency_key(
+    idempotency: &dyn IdempotencyStore,
+    org_id: &str,
+    key: &str,
+    notification_id: &str,
+    channel: NotificationChannel,
+) -> Result<Option<String>, HttpError> {
+    let reservation = idempotency
+        .reserve(org_id, key, notification_id, channel)
         .await
         .map_err(|err| {
-            warn!("Failed to publish email notification: {:?}", err);
+            warn!("Failed to reserve idempotency key {}: {:?}", key, err);
 
             HttpError {
                 status_code: StatusCode::INTERNAL_SERVER_ERROR,
@@ -70,9 +894,255 @@ pub async fn create_email_notification(
             }
         })?;
 
-    info!("Email notification published successfully");
+    match reservation {
+        Reservation::Reserved => Ok(None),
+        // Replaying it could hand out a notification that ends up never
+        // being published
+        Reservation::Pending => Err(HttpError {
+            status_code: StatusCode::CONFLICT,
+            message: format!(
+                "Idempotency key {} is used by a request still in progress",
+                key
+            ),
+        }),
+        Reservation::Existing {
+            notification_id,
+            channel: existing_channel,
+        } if existing_channel == channel => Ok(Some(notification_id)),
+        Reservation::Existing {
+            channel: existing_channel,
+            ..
+        } => Err(HttpError {
+            status_code: StatusCode::UNPROCESSABLE_ENTITY,
+            message: format!(
+                "Idempotency key {} was already used for a {} notification",
+                key,
+                existing_channel.as_str()
+            ),
+        }),
+    }
+}
 
-    Ok(Json(CreateNotificationResponse {
-        id: notification.id,
-    }))
+/// Lets later requests with the idempotency key replay the published
+/// notification
+async fn complete_idempotency_key(
+    idempotency: &dyn IdempotencyStore,
+    org_id: &str,
+    key: Option<&str>,
+    notification_id: &str,
+) {
+    let Some(key) = key else {
+        return;
+    };
+
+    if let Err(err) = idempotency.complete(org_id, key, notification_id).await {
+        warn!("Failed to complete idempotency key {}: {:?}", key, err);
+    }
+}
+
+/// Frees the idempotency key of a request that failed, so the client can
+/// retry it
+async fn release_idempotency_key(
+    idempotency: &dyn IdempotencyStore,
+    org_id: &str,
+    key: Option<&str>,
+    notification_id: &str,
+) {
+    let Some(key) = key else {
+        return;
+    };
+
+    if let Err(err) = idempotency.release(org_id, key, notification_id).await {
+        warn!("Failed to release idempotency key {}: {:?}", key, err);
+    }
+}
+
+async fn record_status(
+    statuses: &dyn NotificationStatusStore,
+    id: &str,
+    status: NotificationStatus,
+    update: StatusUpdate,
+) {
+    if let Err(err) = statuses.transition(id, status, update).await {
+        warn!(
+            "Failed to record notification {} as {:?}: {:?}",
+            id, status, err
+        );
+    }
+}
+
+/// The default version of a template managed by the organization. Shared
+/// templates are not managed through the API, so they are not found.
+async fn owned_template(
+    repository: &dyn WritableEmailTemplateRepository,
+    key: &TemplateKey,
+) -> Result<EmailTemplate, HttpError> {
+    repository.find_by_id(key).await.map_err(|err| match err {
+        TemplateError::NotFound(_) => HttpError {
+            status_code: StatusCode::NOT_FOUND,
+            message: format!("Template not found: {}", key.id),
+        },
+        err => template_http_error(err),
+    })
+}
+
+/// Compiles the template with the current partials and layouts, then saves
+/// it and publishes it as a new version
+async fn save_email_template(
+    email_templates: &EmailTemplateCache,
+    repository: &dyn WritableEmailTemplateRepository,
+    template: EmailTemplate,
+) -> Result<EmailTemplateResponse, HttpError> {
+    email_templates
+        .check(&template)
+        .await
+        .map_err(template_http_error)?;
+
+    repository
+        .save(&template)
+        .await
+        .map_err(template_http_error)?;
+
+    info!(
+        "Saved email template {} of organization {}",
+        template.id,
+        template.organization_id.as_deref().unwrap_or_default()
+    );
+
+    let published = email_templates
+        .record(&template.key())
+        .await
+        .map_err(version_http_error)?;
+
+    Ok(EmailTemplateResponse {
+        published_version: Some(published.version),
+        template,
+    })
+}
+
+fn template_http_error(err: TemplateError) -> HttpError {
+    match err {
+        TemplateError::NotFound(_) => HttpError {
+            status_code: StatusCode::NOT_FOUND,
+            message: err.to_string(),
+        },
+        TemplateError::InvalidId(_)
+        | TemplateError::InvalidLocale(_)
+        | TemplateError::InvalidTemplate { .. }
+        | TemplateError::ValidationFailed(_)
+        | TemplateError::BrokenPartials(_)
+        | TemplateError::MissingVariable { .. }
+        | TemplateError::RenderError(_) => HttpError {
+            status_code: StatusCode::UNPROCESSABLE_ENTITY,
+            message: err.to_string(),
+        },
+        TemplateError::StoreError(_) => {
+            warn!("Failed to access email templates: {:?}", err);
+
+            HttpError {
+                status_code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Internal server error".to_string(),
+            }
+        }
+    }
+}
+
+fn version_http_error(err: VersionError) -> HttpError {
+    match err {
+        VersionError::NotFound { .. } => HttpError {
+            status_code: StatusCode::NOT_FOUND,
+            message: err.to_string(),
+        },
+        VersionError::NothingToRollBack(_) => HttpError {
+            status_code: StatusCode::CONFLICT,
+            message: err.to_string(),
+        },
+        VersionError::TemplateError(err) => template_http_error(err),
+        VersionError::StoreError(_) => {
+            warn!("Failed to access template versions: {:?}", err);
+
+            HttpError {
+                status_code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Internal server error".to_string(),
+            }
+        }
+    }
+}
+
+fn publish_http_error(err: PublishError) -> HttpError {
+    match err {
+        PublishError::Unroutable(_) => HttpError {
+            status_code: StatusCode::UNPROCESSABLE_ENTITY,
+            message: err.to_string(),
+        },
+        PublishError::Nacked | PublishError::Timeout | PublishError::ChannelError(_) => HttpError {
+            status_code: StatusCode::SERVICE_UNAVAILABLE,
+            message: "Message broker unavailable".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{idempotency::sqlite::SqliteIdempotencyStore, infra::sqlite};
+
+    async fn store() -> SqliteIdempotencyStore {
+        let window = Duration::from_secs(3600);
+
+        SqliteIdempotencyStore::new(sqlite::memory().await, window, window)
+            .await
+            .unwrap()
+    }
+
+    async fn claim(
+        store: &SqliteIdempotencyStore,
+        notification_id: &str,
+        channel: NotificationChannel,
+    ) -> Result<Option<String>, HttpError> {
+        claim_idempotency_key(store, "org1", "key-1", notification_id, channel).await
+    }
+
+    #[tokio::test]
+    async fn claiming_a_key_in_progress_conflicts() {
+        let store = store().await;
+
+        assert_eq!(
+            claim(&store, "n1", NotificationChannel::Email)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let err = claim(&store, "n2", NotificationChannel::Email)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.status_code, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn claiming_a_completed_key_replays_it_on_the_same_channel_only() {
+        let store = store().await;
+
+        claim(&store, "n1", NotificationChannel::Email)
+            .await
+            .unwrap();
+        store.complete("org1", "key-1", "n1").await.unwrap();
+
+        assert_eq!(
+            claim(&store, "n2", NotificationChannel::Email)
+                .await
+                .unwrap(),
+            Some("n1".to_string())
+        );
+
+        let err = claim(&store, "n3", NotificationChannel::Sms)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+    }
 }
@@ -1,18 +1,293 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
+use crate::{
+    domain::notification::{NotificationChannel, NotificationStatus},
+    status::store::{NotificationRecord, StatusEvent},
+    templates::email::{
+        engine::RenderedEmail,
+        format::TemplateFormat,
+        locale::LOCALE,
+        processing::HtmlProcessing,
+        repository::TemplatePartial,
+        template::{EmailTemplate, TEMPLATE_ID},
+        variables::TemplateVariable,
+        versions::store::TemplateVersion,
+    },
+};
+
+static E164_PHONE_NUMBER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\+[1-9]\d{1,14}$").unwrap());
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct CreateEmailNotificationRequest {
     #[validate(length(min = 1, message = "Organization ID is required"))]
     pub organization_id: String,
     #[validate(email(message = "Invalid e-mail"))]
     pub recipient: String,
-    #[validate(length(min = 1, message = "Template ID is required"))]
+    #[validate(regex(
+        path = *TEMPLATE_ID,
+        message = "Template ID must be alphanumeric, dashes or underscores"
+    ))]
+    pub template_id: String,
+    #[validate(regex(path = *LOCALE, message = "Locale must be a language tag like pt-BR"))]
+    pub locale: Option<String>,
+    pub metadata: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateSmsNotificationRequest {
+    #[validate(length(min = 1, message = "Organization ID is required"))]
+    pub organization_id: String,
+    #[validate(regex(
+        path = *E164_PHONE_NUMBER,
+        message = "Phone number must be in E.164 format"
+    ))]
+    pub phone_number: String,
+    #[validate(regex(
+        path = *TEMPLATE_ID,
+        message = "Template ID must be alphanumeric, dashes or underscores"
+    ))]
     pub template_id: String,
     pub metadata: serde_json::Value,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreatePushNotificationRequest {
+    #[validate(length(min = 1, message = "Organization ID is required"))]
+    pub organization_id: String,
+    #[validate(length(min = 1, message = "Device token is required"))]
+    pub device_token: String,
+    pub module: Option<String>,
+    #[validate(length(min = 1, message = "Title is required"))]
+    pub title: String,
+    pub description: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct CreateNotificationResponse {
     pub id: String,
 }
+
+#[derive(Debug, Serialize)]
+pub struct NotificationStatusEventResponse {
+    pub status: NotificationStatus,
+    pub detail: Option<String>,
+    pub occurred_at: String,
+}
+
+impl From<StatusEvent> for NotificationStatusEventResponse {
+    fn from(event: StatusEvent) -> Self {
+        Self {
+            status: event.status,
+            detail: event.detail,
+            occurred_at: event.occurred_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotificationStatusResponse {
+    pub id: String,
+    pub organization_id: String,
+    pub channel: NotificationChannel,
+    pub status: NotificationStatus,
+    pub provider_message_id: Option<String>,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub history: Vec<NotificationStatusEventResponse>,
+}
+
+impl From<NotificationRecord> for NotificationStatusResponse {
+    fn from(record: NotificationRecord) -> Self {
+        Self {
+            id: record.id,
+            organization_id: record.organization_id,
+            channel: record.channel,
+            status: record.status,
+            provider_message_id: record.provider_message_id,
+            error: record.error,
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+            history: record.history.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateOrganizationRequest {
+    #[validate(length(min = 1, message = "Organization ID is required"))]
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrganizationResponse {
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListOrganizationsResponse {
+    pub organizations: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthcheckResponse {
+    pub status: &'static str,
+}
+
+/// The content of an email template, or of one of its translations when
+/// `locale` is set
+#[derive(Debug, Deserialize, Validate)]
+pub struct EmailTemplateRequest {
+    #[validate(regex(path = *LOCALE, message = "Locale must be a language tag like pt-BR"))]
+    pub locale: Option<String>,
+    #[validate(length(min = 1, message = "Subject is required"))]
+    pub subject: String,
+    #[validate(length(min = 1, message = "Body is required"))]
+    pub body: String,
+    #[serde(default)]
+    pub format: TemplateFormat,
+    pub text_body: Option<String>,
+    #[serde(default)]
+    pub variables: Vec<TemplateVariable>,
+    pub strict: Option<bool>,
+    pub layout: Option<String>,
+    pub preheader: Option<String>,
+    #[serde(default)]
+    pub processing: HtmlProcessing,
+}
+
+impl EmailTemplateRequest {
+    pub fn into_template(self, id: String, organization_id: String) -> EmailTemplate {
+        EmailTemplate {
+            id,
+            organization_id: Some(organization_id),
+            locale: self.locale,
+            subject: self.subject,
+            body: self.body,
+            format: self.format,
+            text_body: self.text_body,
+            variables: self.variables,
+            strict: self.strict,
+            layout: self.layout,
+            preheader: self.preheader,
+            processing: self.processing,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateEmailTemplateRequest {
+    #[validate(regex(
+        path = *TEMPLATE_ID,
+        message = "Template ID must be alphanumeric, dashes or underscores"
+    ))]
+    pub id: String,
+    #[serde(flatten)]
+    #[validate(nested)]
+    pub template: EmailTemplateRequest,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TemplateLocaleQuery {
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmailTemplateResponse {
+    #[serde(flatten)]
+    pub template: EmailTemplate,
+    pub published_version: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmailTemplateSummary {
+    pub id: String,
+    pub locales: Vec<String>,
+    pub published_version: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListEmailTemplatesResponse {
+    pub templates: Vec<EmailTemplateSummary>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PreviewTemplateRequest {
+    #[validate(regex(path = *LOCALE, message = "Locale must be a language tag like pt-BR"))]
+    pub locale: Option<String>,
+    /// Renders the published version when not set
+    pub version: Option<i64>,
+    pub metadata: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct TestSendTemplateRequest {
+    #[validate(email(message = "Invalid e-mail"))]
+    pub recipient: String,
+    #[serde(flatten)]
+    #[validate(nested)]
+    pub preview: PreviewTemplateRequest,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RenderedEmailResponse {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl From<RenderedEmail> for RenderedEmailResponse {
+    fn from(rendered: RenderedEmail) -> Self {
+        Self {
+            subject: rendered.subject,
+            html: rendered.html,
+            text: rendered.text,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestSendResponse {
+    pub provider_message_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateVersionSummary {
+    pub version: i64,
+    pub created_at: String,
+    pub published: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListTemplateVersionsResponse {
+    pub template_id: String,
+    pub versions: Vec<TemplateVersionSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateVersionResponse {
+    pub template_id: String,
+    pub version: i64,
+    pub created_at: String,
+    pub published: bool,
+    pub templates: Vec<EmailTemplate>,
+    pub partials: Vec<TemplatePartial>,
+    pub layouts: Vec<TemplatePartial>,
+}
+
+impl From<TemplateVersion> for TemplateVersionResponse {
+    fn from(version: TemplateVersion) -> Self {
+        Self {
+            template_id: version.key.id,
+            version: version.version,
+            created_at: version.created_at,
+            published: version.published,
+            templates: version.snapshot.templates,
+            partials: version.snapshot.partials,
+            layouts: version.snapshot.layouts,
+        }
+    }
+}
@@ -4,13 +4,31 @@ use axum::{
     Json, Router,
 };
 
-use crate::infra::amqp::AmqpPublisher;
+use std::sync::Arc;
+
+use crate::{
+    idempotency::store::IdempotencyStore,
+    infra::{
+        amqp::AmqpPublisher, connection::ConnectionManager, organizations::OrganizationRegistry,
+    },
+    providers::email::{EmailSender, TestRecipients},
+    status::store::NotificationStatusStore,
+    templates::email::{cache::EmailTemplateCache, repository::WritableEmailTemplateRepository},
+};
 
 use super::handlers;
 
 #[derive(Clone)]
 pub struct AppState {
     pub publisher: AmqpPublisher,
+    pub organizations: Arc<OrganizationRegistry>,
+    pub statuses: Arc<dyn NotificationStatusStore>,
+    pub idempotency: Arc<dyn IdempotencyStore>,
+    pub email_templates: Arc<EmailTemplateCache>,
+    pub email_template_repository: Arc<dyn WritableEmailTemplateRepository>,
+    pub email_sender: Arc<EmailSender>,
+    pub test_recipients: Arc<TestRecipients>,
+    pub connection: Arc<ConnectionManager>,
 }
 
 impl FromRef<AppState> for AmqpPublisher {
@@ -19,20 +37,109 @@ impl FromRef<AppState> for AmqpPublisher {
     }
 }
 
-pub type HttpResponse<T> = Json<T>;
+impl FromRef<AppState> for Arc<OrganizationRegistry> {
+    fn from_ref(state: &AppState) -> Arc<OrganizationRegistry> {
+        state.organizations.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn NotificationStatusStore> {
+    fn from_ref(state: &AppState) -> Arc<dyn NotificationStatusStore> {
+        state.statuses.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn IdempotencyStore> {
+    fn from_ref(state: &AppState) -> Arc<dyn IdempotencyStore> {
+        state.idempotency.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<EmailTemplateCache> {
+    fn from_ref(state: &AppState) -> Arc<EmailTemplateCache> {
+        state.email_templates.clone()
+    }
+}
 
-pub fn create_router(publisher: AmqpPublisher) -> Router {
-    let app_state = AppState { publisher };
+impl FromRef<AppState> for Arc<dyn WritableEmailTemplateRepository> {
+    fn from_ref(state: &AppState) -> Arc<dyn WritableEmailTemplateRepository> {
+        state.email_template_repository.clone()
+    }
+}
 
+impl FromRef<AppState> for Arc<EmailSender> {
+    fn from_ref(state: &AppState) -> Arc<EmailSender> {
+        state.email_sender.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<TestRecipients> {
+    fn from_ref(state: &AppState) -> Arc<TestRecipients> {
+        state.test_recipients.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<ConnectionManager> {
+    fn from_ref(state: &AppState) -> Arc<ConnectionManager> {
+        state.connection.clone()
+    }
+}
+
+pub type HttpResponse<T> = Json<T>;
+
+pub fn create_router(app_state: AppState) -> Router {
     Router::new()
-        .route("/healthcheck", get(healthcheck))
+        .route("/healthcheck", get(handlers::healthcheck))
         .route(
             "/email-notification",
             post(handlers::create_email_notification),
         )
+        .route("/sms-notification", post(handlers::create_sms_notification))
+        .route(
+            "/push-notification",
+            post(handlers::create_push_notification),
+        )
+        .route(
+            "/organizations/:org_id/notifications/:id",
+            get(handlers::get_notification),
+        )
+        .route(
+            "/organizations",
+            get(handlers::list_organizations).post(handlers::register_organization),
+        )
+        .route(
+            "/organizations/:org_id/templates",
+            get(handlers::list_email_templates).post(handlers::create_email_template),
+        )
+        .route(
+            "/organizations/:org_id/templates/:id",
+            get(handlers::get_email_template)
+                .put(handlers::update_email_template)
+                .delete(handlers::delete_email_template),
+        )
+        .route(
+            "/organizations/:org_id/templates/:id/preview",
+            post(handlers::preview_template),
+        )
+        .route(
+            "/organizations/:org_id/templates/:id/test-send",
+            post(handlers::test_send_template),
+        )
+        .route(
+            "/organizations/:org_id/templates/:id/versions",
+            get(handlers::list_template_versions),
+        )
+        .route(
+            "/organizations/:org_id/templates/:id/versions/:version",
+            get(handlers::get_template_version),
+        )
+        .route(
+            "/organizations/:org_id/templates/:id/versions/:version/publish",
+            post(handlers::publish_template_version),
+        )
+        .route(
+            "/organizations/:org_id/templates/:id/rollback",
+            post(handlers::rollback_template),
+        )
         .with_state(app_state)
 }
-
-async fn healthcheck() -> &'static str {
-    "OK"
-}
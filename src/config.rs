@@ -1,15 +1,70 @@
-use std::env;
+use std::{env, str::FromStr};
 
 use once_cell::sync::Lazy;
 
 use crate::tracing::error;
 
+/// Where email templates are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplatesBackend {
+    /// JSON files under `EMAIL_TEMPLATES_PATH`
+    File,
+    /// The `email_templates` tables of `EMAIL_TEMPLATES_DATABASE_URL`
+    Sqlite,
+}
+
+impl FromStr for EmailTemplatesBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "file" => Ok(EmailTemplatesBackend::File),
+            "sqlite" => Ok(EmailTemplatesBackend::Sqlite),
+            other => Err(format!("Unknown email templates backend: {}", other)),
+        }
+    }
+}
+
 pub struct Config {
     pub port: String,
     pub rabbitmq_host: String,
     pub rabbitmq_port: u16,
     pub rabbitmq_user: String,
     pub rabbitmq_password: String,
+    /// SMS notifications are disabled unless every Twilio setting is set
+    pub twilio_account_sid: Option<String>,
+    pub twilio_auth_token: Option<String>,
+    pub twilio_from_number: Option<String>,
+    /// Push notifications are disabled when unset
+    pub fcm_service_account_path: Option<String>,
+    pub organizations: Vec<String>,
+    pub max_delivery_attempts: u32,
+    pub retry_base_delay_ms: u64,
+    pub database_url: String,
+    pub publish_confirm_timeout_ms: u64,
+    pub reconnect_base_delay_ms: u64,
+    pub reconnect_max_delay_ms: u64,
+    pub publisher_channels: usize,
+    pub email_consumer_prefetch: u16,
+    pub sms_consumer_prefetch: u16,
+    pub push_consumer_prefetch: u16,
+    pub idempotency_window_secs: u64,
+    pub email_templates_backend: EmailTemplatesBackend,
+    pub email_templates_path: String,
+    pub email_templates_database_url: String,
+    pub sms_templates_path: String,
+    pub watch_email_templates: bool,
+    pub email_templates_poll_interval_secs: u64,
+    pub email_templates_warn_only: bool,
+    pub strict_email_templates: bool,
+    pub test_email_recipients: Vec<String>,
+}
+
+/// Settings of the `import-email-templates` command, which runs without the
+/// broker and the server
+pub struct ImportConfig {
+    pub email_templates_path: String,
+    pub email_templates_database_url: String,
 }
 
 static CONFIG: Lazy<Config> = Lazy::new(|| {
@@ -18,6 +73,57 @@ static CONFIG: Lazy<Config> = Lazy::new(|| {
     let rabbitmq_port = get_env("RABBITMQ_PORT").parse().unwrap();
     let rabbitmq_user = get_env("RABBITMQ_USER");
     let rabbitmq_password = get_env("RABBITMQ_PASSWORD");
+    let twilio_account_sid = get_optional_env("TWILIO_ACCOUNT_SID");
+    let twilio_auth_token = get_optional_env("TWILIO_AUTH_TOKEN");
+    let twilio_from_number = get_optional_env("TWILIO_FROM_NUMBER");
+    let fcm_service_account_path = get_optional_env("FCM_SERVICE_ACCOUNT_PATH");
+    let organizations = get_list_env("ORGANIZATIONS");
+    let max_delivery_attempts = get_env_or("MAX_DELIVERY_ATTEMPTS", "5").parse().unwrap();
+    let retry_base_delay_ms = get_env_or("RETRY_BASE_DELAY_MS", "1000").parse().unwrap();
+    let database_url = get_database_url();
+    let publish_confirm_timeout_ms = get_env_or("PUBLISH_CONFIRM_TIMEOUT_MS", "5000")
+        .parse()
+        .unwrap();
+    let reconnect_base_delay_ms = get_env_or("RECONNECT_BASE_DELAY_MS", "1000")
+        .parse()
+        .unwrap();
+    let reconnect_max_delay_ms = get_env_or("RECONNECT_MAX_DELAY_MS", "30000")
+        .parse()
+        .unwrap();
+    let publisher_channels = get_env_or("PUBLISHER_CHANNELS", "1").parse().unwrap();
+    let consumer_prefetch = get_env_or("CONSUMER_PREFETCH", "10");
+    let email_consumer_prefetch = get_env_or("EMAIL_CONSUMER_PREFETCH", &consumer_prefetch)
+        .parse()
+        .unwrap();
+    let sms_consumer_prefetch = get_env_or("SMS_CONSUMER_PREFETCH", &consumer_prefetch)
+        .parse()
+        .unwrap();
+    let push_consumer_prefetch = get_env_or("PUSH_CONSUMER_PREFETCH", &consumer_prefetch)
+        .parse()
+        .unwrap();
+    let idempotency_window_secs = get_env_or("IDEMPOTENCY_WINDOW_SECS", "86400")
+        .parse()
+        .unwrap();
+    let email_templates_backend = get_env_or("EMAIL_TEMPLATES_BACKEND", "file")
+        .parse()
+        .unwrap();
+    let email_templates_path = get_email_templates_path();
+    let email_templates_database_url = get_email_templates_database_url(&database_url);
+    let sms_templates_path = get_env_or(
+        "SMS_TEMPLATES_PATH",
+        &format!("{}/sms", email_templates_path),
+    );
+    let watch_email_templates = get_env_or("WATCH_EMAIL_TEMPLATES", "true").parse().unwrap();
+    let email_templates_poll_interval_secs = get_env_or("EMAIL_TEMPLATES_POLL_INTERVAL_SECS", "30")
+        .parse()
+        .unwrap();
+    let email_templates_warn_only = get_env_or("EMAIL_TEMPLATES_WARN_ONLY", "false")
+        .parse()
+        .unwrap();
+    let strict_email_templates = get_env_or("STRICT_EMAIL_TEMPLATES", "false")
+        .parse()
+        .unwrap();
+    let test_email_recipients = get_list_env("TEST_EMAIL_RECIPIENTS");
 
     Config {
         port,
@@ -25,6 +131,31 @@ static CONFIG: Lazy<Config> = Lazy::new(|| {
         rabbitmq_port,
         rabbitmq_user,
         rabbitmq_password,
+        twilio_account_sid,
+        twilio_auth_token,
+        twilio_from_number,
+        fcm_service_account_path,
+        organizations,
+        max_delivery_attempts,
+        retry_base_delay_ms,
+        database_url,
+        publish_confirm_timeout_ms,
+        reconnect_base_delay_ms,
+        reconnect_max_delay_ms,
+        publisher_channels,
+        email_consumer_prefetch,
+        sms_consumer_prefetch,
+        push_consumer_prefetch,
+        idempotency_window_secs,
+        email_templates_backend,
+        email_templates_path,
+        email_templates_database_url,
+        sms_templates_path,
+        watch_email_templates,
+        email_templates_poll_interval_secs,
+        email_templates_warn_only,
+        strict_email_templates,
+        test_email_recipients,
     }
 });
 
@@ -32,9 +163,45 @@ pub fn get_config() -> &'static Config {
     &CONFIG
 }
 
+pub fn get_import_config() -> ImportConfig {
+    ImportConfig {
+        email_templates_path: get_email_templates_path(),
+        email_templates_database_url: get_email_templates_database_url(&get_database_url()),
+    }
+}
+
+fn get_database_url() -> String {
+    get_env_or("DATABASE_URL", "sqlite://notifications.db")
+}
+
+fn get_email_templates_path() -> String {
+    get_env_or("EMAIL_TEMPLATES_PATH", "templates")
+}
+
+fn get_email_templates_database_url(database_url: &str) -> String {
+    get_env_or("EMAIL_TEMPLATES_DATABASE_URL", database_url)
+}
+
 fn get_env(name: &str) -> String {
     env::var(name).unwrap_or_else(|_err| {
         error!("{} is not set", name);
         std::process::exit(1);
     })
 }
+
+fn get_env_or(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_err| default.to_string())
+}
+
+fn get_optional_env(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn get_list_env(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
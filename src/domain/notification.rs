@@ -30,6 +30,17 @@ pub trait Notification: Serialize + for<'de> Deserialize<'de> {
 pub struct EmailNotification {
     pub id: String,
     pub template_id: String,
+    /// Organization that manages the template, `None` for shared templates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_organization_id: Option<String>,
+    /// Version of the template the notification renders, the published one
+    /// when it was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_version: Option<i64>,
+    /// Locale the template is rendered in, falling back to the default
+    /// template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
     pub recipient: String,
     pub created_at: String,
     pub metadata: serde_json::Value,
@@ -38,10 +49,20 @@ pub struct EmailNotification {
 impl Notification for EmailNotification {}
 
 impl EmailNotification {
-    pub fn new(template_id: String, recipient: String, metadata: serde_json::Value) -> Self {
+    pub fn new(
+        template_id: String,
+        template_organization_id: Option<String>,
+        template_version: Option<i64>,
+        locale: Option<String>,
+        recipient: String,
+        metadata: serde_json::Value,
+    ) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             template_id,
+            template_organization_id,
+            template_version,
+            locale,
             recipient,
             created_at: Utc::now().to_rfc3339(),
             metadata,
@@ -51,18 +72,121 @@ impl EmailNotification {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SMSNotification {
+    pub id: String,
+    pub template_id: String,
     pub phone_number: String,
-    pub message: String,
+    pub created_at: String,
+    pub metadata: serde_json::Value,
 }
 
 impl Notification for SMSNotification {}
 
+impl SMSNotification {
+    pub fn new(template_id: String, phone_number: String, metadata: serde_json::Value) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            template_id,
+            phone_number,
+            created_at: Utc::now().to_rfc3339(),
+            metadata,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PushNotification {
+    pub id: String,
     pub device_token: String,
     pub module: Option<String>,
     pub title: String,
     pub description: Option<String>,
+    pub created_at: String,
 }
 
 impl Notification for PushNotification {}
+
+impl PushNotification {
+    pub fn new(
+        device_token: String,
+        module: Option<String>,
+        title: String,
+        description: Option<String>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            device_token,
+            module,
+            title,
+            description,
+            created_at: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationChannel {
+    Email,
+    Sms,
+    Push,
+}
+
+impl NotificationChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationChannel::Email => "email",
+            NotificationChannel::Sms => "sms",
+            NotificationChannel::Push => "push",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "email" => Some(NotificationChannel::Email),
+            "sms" => Some(NotificationChannel::Sms),
+            "push" => Some(NotificationChannel::Push),
+            _ => None,
+        }
+    }
+}
+
+/// Lifecycle of a notification, from the API request to its delivery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationStatus {
+    Accepted,
+    Published,
+    Processing,
+    /// Failed an attempt and waits for the next one
+    Retrying,
+    Sent,
+    Failed,
+    DeadLettered,
+}
+
+impl NotificationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationStatus::Accepted => "accepted",
+            NotificationStatus::Published => "published",
+            NotificationStatus::Processing => "processing",
+            NotificationStatus::Retrying => "retrying",
+            NotificationStatus::Sent => "sent",
+            NotificationStatus::Failed => "failed",
+            NotificationStatus::DeadLettered => "dead_lettered",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "accepted" => Some(NotificationStatus::Accepted),
+            "published" => Some(NotificationStatus::Published),
+            "processing" => Some(NotificationStatus::Processing),
+            "retrying" => Some(NotificationStatus::Retrying),
+            "sent" => Some(NotificationStatus::Sent),
+            "failed" => Some(NotificationStatus::Failed),
+            "dead_lettered" => Some(NotificationStatus::DeadLettered),
+            _ => None,
+        }
+    }
+}
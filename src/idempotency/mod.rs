@@ -0,0 +1,2 @@
+pub mod sqlite;
+pub mod store;
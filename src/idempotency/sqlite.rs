@@ -0,0 +1,298 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{FromRow, SqlitePool};
+
+use super::store::{IdempotencyError, IdempotencyStore, Reservation};
+use crate::domain::notification::NotificationChannel;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    organization_id TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    notification_id TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    completed INTEGER NOT NULL DEFAULT 0,
+    expires_at INTEGER NOT NULL,
+    PRIMARY KEY (organization_id, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_at
+    ON idempotency_keys (expires_at);
+"#;
+
+#[derive(FromRow)]
+struct IdempotencyKeyRow {
+    notification_id: String,
+    channel: String,
+    completed: bool,
+}
+
+/// Keeps idempotency keys for `window`, after which the same key creates a
+/// new notification. Keys claimed by a request that never completed, like
+/// one interrupted by a crash, are taken over once `pending_ttl` has passed.
+pub struct SqliteIdempotencyStore {
+    pool: SqlitePool,
+    window: Duration,
+    pending_ttl: Duration,
+}
+
+impl SqliteIdempotencyStore {
+    pub async fn new(
+        pool: SqlitePool,
+        window: Duration,
+        pending_ttl: Duration,
+    ) -> Result<Self, IdempotencyError> {
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .map_err(store_error)?;
+
+        Ok(Self {
+            pool,
+            window,
+            pending_ttl,
+        })
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for SqliteIdempotencyStore {
+    async fn reserve(
+        &self,
+        organization_id: &str,
+        key: &str,
+        notification_id: &str,
+        channel: NotificationChannel,
+    ) -> Result<Reservation, IdempotencyError> {
+        let now = Utc::now().timestamp();
+        let expires_at = now.saturating_add(self.pending_ttl.as_secs() as i64);
+
+        let mut transaction = self.pool.begin().await.map_err(store_error)?;
+
+        sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= ?")
+            .bind(now)
+            .execute(&mut *transaction)
+            .await
+            .map_err(store_error)?;
+
+        sqlx::query(
+            "INSERT OR IGNORE INTO idempotency_keys (organization_id, idempotency_key, notification_id, channel, expires_at)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(organization_id)
+        .bind(key)
+        .bind(notification_id)
+        .bind(channel.as_str())
+        .bind(expires_at)
+        .execute(&mut *transaction)
+        .await
+        .map_err(store_error)?;
+
+        let row: IdempotencyKeyRow = sqlx::query_as(
+            "SELECT notification_id, channel, completed
+             FROM idempotency_keys
+             WHERE organization_id = ? AND idempotency_key = ?",
+        )
+        .bind(organization_id)
+        .bind(key)
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(store_error)?;
+
+        transaction.commit().await.map_err(store_error)?;
+
+        if row.notification_id == notification_id {
+            return Ok(Reservation::Reserved);
+        }
+
+        if !row.completed {
+            return Ok(Reservation::Pending);
+        }
+
+        let channel = NotificationChannel::parse(&row.channel).ok_or_else(|| {
+            IdempotencyError::StoreError(format!("Unknown notification channel: {}", row.channel))
+        })?;
+
+        Ok(Reservation::Existing {
+            notification_id: row.notification_id,
+            channel,
+        })
+    }
+
+    async fn complete(
+        &self,
+        organization_id: &str,
+        key: &str,
+        notification_id: &str,
+    ) -> Result<(), IdempotencyError> {
+        let expires_at = Utc::now()
+            .timestamp()
+            .saturating_add(self.window.as_secs() as i64);
+
+        // Completed keys are kept for the whole window, from the moment the
+        // notification was accepted
+        sqlx::query(
+            "UPDATE idempotency_keys SET completed = 1, expires_at = ?
+             WHERE organization_id = ? AND idempotency_key = ? AND notification_id = ?",
+        )
+        .bind(expires_at)
+        .bind(organization_id)
+        .bind(key)
+        .bind(notification_id)
+        .execute(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(())
+    }
+
+    async fn release(
+        &self,
+        organization_id: &str,
+        key: &str,
+        notification_id: &str,
+    ) -> Result<(), IdempotencyError> {
+        sqlx::query(
+            "DELETE FROM idempotency_keys
+             WHERE organization_id = ? AND idempotency_key = ? AND notification_id = ?",
+        )
+        .bind(organization_id)
+        .bind(key)
+        .bind(notification_id)
+        .execute(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(())
+    }
+}
+
+fn store_error(err: sqlx::Error) -> IdempotencyError {
+    IdempotencyError::StoreError(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::sqlite;
+
+    const WINDOW: Duration = Duration::from_secs(3600);
+
+    async fn store(pending_ttl: Duration) -> SqliteIdempotencyStore {
+        SqliteIdempotencyStore::new(sqlite::memory().await, WINDOW, pending_ttl)
+            .await
+            .unwrap()
+    }
+
+    async fn reserve(
+        store: &SqliteIdempotencyStore,
+        notification_id: &str,
+        channel: NotificationChannel,
+    ) -> Reservation {
+        store
+            .reserve("org1", "key-1", notification_id, channel)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_key_in_progress_is_pending_for_other_requests() {
+        let store = store(WINDOW).await;
+
+        assert_eq!(
+            reserve(&store, "n1", NotificationChannel::Email).await,
+            Reservation::Reserved
+        );
+        assert_eq!(
+            reserve(&store, "n2", NotificationChannel::Email).await,
+            Reservation::Pending
+        );
+    }
+
+    #[tokio::test]
+    async fn a_completed_key_replays_its_notification() {
+        let store = store(WINDOW).await;
+
+        reserve(&store, "n1", NotificationChannel::Email).await;
+        store.complete("org1", "key-1", "n1").await.unwrap();
+
+        assert_eq!(
+            reserve(&store, "n2", NotificationChannel::Email).await,
+            Reservation::Existing {
+                notification_id: "n1".to_string(),
+                channel: NotificationChannel::Email,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn a_completed_key_reports_the_channel_it_was_used_for() {
+        let store = store(WINDOW).await;
+
+        reserve(&store, "n1", NotificationChannel::Sms).await;
+        store.complete("org1", "key-1", "n1").await.unwrap();
+
+        // The handler rejects the key when the channels differ
+        assert_eq!(
+            reserve(&store, "n2", NotificationChannel::Push).await,
+            Reservation::Existing {
+                notification_id: "n1".to_string(),
+                channel: NotificationChannel::Sms,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn an_expired_pending_claim_is_taken_over() {
+        let store = store(Duration::ZERO).await;
+
+        reserve(&store, "n1", NotificationChannel::Email).await;
+
+        assert_eq!(
+            reserve(&store, "n2", NotificationChannel::Email).await,
+            Reservation::Reserved
+        );
+
+        // The interrupted request can no longer complete the key
+        store.complete("org1", "key-1", "n1").await.unwrap();
+        store.complete("org1", "key-1", "n2").await.unwrap();
+
+        assert_eq!(
+            reserve(&store, "n3", NotificationChannel::Email).await,
+            Reservation::Existing {
+                notification_id: "n2".to_string(),
+                channel: NotificationChannel::Email,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn a_released_key_can_be_claimed_again() {
+        let store = store(WINDOW).await;
+
+        reserve(&store, "n1", NotificationChannel::Email).await;
+        store.release("org1", "key-1", "n1").await.unwrap();
+
+        assert_eq!(
+            reserve(&store, "n2", NotificationChannel::Email).await,
+            Reservation::Reserved
+        );
+    }
+
+    #[tokio::test]
+    async fn keys_are_scoped_by_organization() {
+        let store = store(WINDOW).await;
+
+        reserve(&store, "n1", NotificationChannel::Email).await;
+
+        assert_eq!(
+            store
+                .reserve("org2", "key-1", "n2", NotificationChannel::Email)
+                .await
+                .unwrap(),
+            Reservation::Reserved
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::domain::notification::NotificationChannel;
+
+#[derive(Error, Debug)]
+pub enum IdempotencyError {
+    #[error("Idempotency store error: {0}")]
+    StoreError(String),
+}
+
+/// Outcome of claiming an idempotency key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reservation {
+    /// The key is now held by the given notification
+    Reserved,
+    /// The key is held by a request that hasn't finished yet, and whose
+    /// claim hasn't expired
+    Pending,
+    /// The key was already claimed by a notification within the window
+    Existing {
+        notification_id: String,
+        channel: NotificationChannel,
+    },
+}
+
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Claims the key of an organization for the notification, unless
+    /// another notification holds it
+    async fn reserve(
+        &self,
+        organization_id: &str,
+        key: &str,
+        notification_id: &str,
+        channel: NotificationChannel,
+    ) -> Result<Reservation, IdempotencyError>;
+
+    /// Marks the key as held by an accepted notification, so later requests
+    /// replay it
+    async fn complete(
+        &self,
+        organization_id: &str,
+        key: &str,
+        notification_id: &str,
+    ) -> Result<(), IdempotencyError>;
+
+    /// Frees a key claimed by a notification that was never accepted, so a
+    /// retry of the request can claim it again
+    async fn release(
+        &self,
+        organization_id: &str,
+        key: &str,
+        notification_id: &str,
+    ) -> Result<(), IdempotencyError>;
+}
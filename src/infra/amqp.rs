@@ -1,252 +1,1050 @@
 use amqprs::{
-    callbacks::{DefaultChannelCallback, DefaultConnectionCallback},
+    callbacks::ChannelCallback,
     channel::{
-        BasicConsumeArguments, BasicNackArguments, BasicPublishArguments, Channel,
-        QueueBindArguments, QueueDeclareArguments,
+        BasicConsumeArguments, BasicNackArguments, BasicPublishArguments, BasicQosArguments,
+        Channel, ConfirmSelectArguments, ExchangeDeclareArguments, QueueBindArguments,
+        QueueDeclareArguments,
     },
-    connection::{Connection, OpenConnectionArguments},
-    BasicProperties, Deliver,
+    connection::Connection,
+    Ack, BasicProperties, Cancel, CloseChannel, Deliver, FieldTable, FieldValue, Nack, Return,
 };
 
 use async_trait::async_trait;
-use serde::Serialize;
-use std::sync::Arc;
-use tokio::sync::OnceCell;
-use tracing::error;
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::{error, warn};
+
+use super::connection::{AmqpError, ChannelCloseNotifier, Recoverable, RecoveryChannelCallback};
+use super::consumer::{ConsumerError, Disposition};
+
+/// Header holding how many times a message has already failed
+pub const ATTEMPT_HEADER: &str = "x-attempt";
+
+/// Retries failed messages through per-attempt delay queues, doubling the
+/// delay on every attempt, and parks them in a `{queue}.dlq` queue once
+/// `max_attempts` is reached. Both are published to by the consumer through
+/// the publisher's confirm-mode channels, so the main queues keep the
+/// arguments they were first declared with.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay_ms: u64) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            base_delay_ms,
+        }
+    }
+
+    /// Delay before the given retry attempt, starting at 1
+    pub fn delay_ms(&self, attempt: u32) -> u64 {
+        let exponent = attempt.saturating_sub(1).min(31);
+
+        self.base_delay_ms.saturating_mul(1 << exponent)
+    }
+
+    /// Whether a failure of this delivery exhausts the message's attempts
+    pub fn is_last_attempt(&self, properties: &BasicProperties) -> bool {
+        failed_attempts(properties) + 1 >= self.max_attempts
+    }
+
+    /// Delay queue of a retry attempt. Queues are named after their delay,
+    /// since their TTL can't change once declared, so changing the delays
+    /// declares new queues instead of failing to redeclare the old ones.
+    pub fn retry_queue(&self, queue: &str, attempt: u32) -> String {
+        format!("{}.retry.{}ms", queue, self.delay_ms(attempt))
+    }
+
+    pub fn dead_letter_queue(queue: &str) -> String {
+        format!("{}.dlq", queue)
+    }
+}
+
+fn field_name(name: &str) -> amqprs::FieldName {
+    name.try_into().expect("AMQP field names are short strings")
+}
+
+/// How many times the message has already failed, according to its
+/// `x-attempt` header
+pub fn failed_attempts(properties: &BasicProperties) -> u32 {
+    let value = properties
+        .headers()
+        .and_then(|headers| headers.get(&field_name(ATTEMPT_HEADER)));
+
+    match value {
+        Some(FieldValue::I(attempt)) => (*attempt).max(0) as u32,
+        Some(FieldValue::i(attempt)) => *attempt,
+        Some(FieldValue::l(attempt)) => (*attempt).clamp(0, u32::MAX as i64) as u32,
+        _ => 0,
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PublishError {
+    #[error("No queue is bound to routing key {0}")]
+    Unroutable(String),
+
+    #[error("Broker rejected the message")]
+    Nacked,
+
+    #[error("Timed out waiting for the broker to confirm the message")]
+    Timeout,
+
+    #[error("Failed to publish message: {0}")]
+    ChannelError(String),
+}
+
+struct PendingPublish {
+    message_id: String,
+    routing_key: String,
+    sender: oneshot::Sender<Result<(), PublishError>>,
+}
+
+/// Publishes waiting for a broker confirmation, indexed by the delivery tag
+/// the broker assigns to them
+#[derive(Default)]
+struct PendingConfirms {
+    last_delivery_tag: u64,
+    pending: BTreeMap<u64, PendingPublish>,
+    returned: HashSet<String>,
+}
+
+impl PendingConfirms {
+    /// Awaits the confirmation of the channel's next publish, returning its
+    /// delivery tag
+    fn register(&mut self, publish: PendingPublish) -> u64 {
+        self.last_delivery_tag += 1;
+        self.pending.insert(self.last_delivery_tag, publish);
+
+        self.last_delivery_tag
+    }
+
+    fn take(&mut self, delivery_tag: u64, multiple: bool) -> Vec<PendingPublish> {
+        if !multiple {
+            return self.pending.remove(&delivery_tag).into_iter().collect();
+        }
+
+        let remaining = self.pending.split_off(&(delivery_tag + 1));
+
+        std::mem::replace(&mut self.pending, remaining)
+            .into_values()
+            .collect()
+    }
+
+    /// Resolves the publishes confirmed by a basic.ack
+    fn ack(&mut self, delivery_tag: u64, multiple: bool) {
+        for publish in self.take(delivery_tag, multiple) {
+            // Unroutable mandatory messages are returned before being acked
+            let result = if self.returned.remove(&publish.message_id) {
+                Err(PublishError::Unroutable(publish.routing_key))
+            } else {
+                Ok(())
+            };
+
+            let _ = publish.sender.send(result);
+        }
+    }
+
+    /// Fails the publishes rejected by a basic.nack
+    fn nack(&mut self, delivery_tag: u64, multiple: bool) {
+        for publish in self.take(delivery_tag, multiple) {
+            self.returned.remove(&publish.message_id);
+
+            let _ = publish.sender.send(Err(PublishError::Nacked));
+        }
+    }
+
+    /// Fails every pending publish, since their confirmations will never
+    /// arrive once the channel is gone
+    fn fail_all(&mut self, reason: &str) {
+        for (_, publish) in std::mem::take(&mut self.pending) {
+            let _ = publish
+                .sender
+                .send(Err(PublishError::ChannelError(reason.to_string())));
+        }
+
+        self.returned.clear();
+    }
+}
+
+/// Resolves pending publishes from the broker's basic.ack, basic.nack and
+/// basic.return frames
+struct PublisherChannelCallback {
+    confirms: Arc<Mutex<PendingConfirms>>,
+    notifier: ChannelCloseNotifier,
+}
+
+#[async_trait]
+impl ChannelCallback for PublisherChannelCallback {
+    async fn close(
+        &mut self,
+        channel: &Channel,
+        close: CloseChannel,
+    ) -> Result<(), amqprs::error::Error> {
+        error!("Publisher channel {} closed: {}", channel, close);
+
+        self.confirms
+            .lock()
+            .unwrap()
+            .fail_all("Publisher channel closed");
+        self.notifier.notify();
+
+        Ok(())
+    }
+
+    async fn cancel(
+        &mut self,
+        _channel: &Channel,
+        _cancel: Cancel,
+    ) -> Result<(), amqprs::error::Error> {
+        Ok(())
+    }
+
+    async fn flow(
+        &mut self,
+        _channel: &Channel,
+        active: bool,
+    ) -> Result<bool, amqprs::error::Error> {
+        Ok(active)
+    }
+
+    async fn publish_ack(&mut self, _channel: &Channel, ack: Ack) {
+        self.confirms
+            .lock()
+            .unwrap()
+            .ack(ack.delivery_tag(), ack.mutiple());
+    }
+
+    async fn publish_nack(&mut self, _channel: &Channel, nack: Nack) {
+        self.confirms
+            .lock()
+            .unwrap()
+            .nack(nack.delivery_tag(), nack.multiple());
+    }
+
+    async fn publish_return(
+        &mut self,
+        _channel: &Channel,
+        ret: Return,
+        basic_properties: BasicProperties,
+        _content: Vec<u8>,
+    ) {
+        warn!("Message returned by the broker: {}", ret);
+
+        if let Some(message_id) = basic_properties.message_id() {
+            self.confirms
+                .lock()
+                .unwrap()
+                .returned
+                .insert(message_id.clone());
+        }
+    }
+}
+
+/// Notification types of every organization, by organization ID
+type QueueTopology = BTreeMap<String, Vec<String>>;
+
+/// A confirm-mode channel along with the publishes awaiting a confirmation
+/// on it
+struct PublisherChannel {
+    channel: Channel,
+    confirms: Arc<Mutex<PendingConfirms>>,
+    // Connection generation the channel was opened on
+    generation: u64,
+    notifier: ChannelCloseNotifier,
+}
 
-static CONNECTION: OnceCell<Arc<Connection>> = OnceCell::const_new();
-static CHANNEL: OnceCell<Arc<Channel>> = OnceCell::const_new();
+// Publishes hold the lock of their slot until the message is sent, since
+// delivery tags are assigned in publish order. `None` until the publisher is
+// registered with the connection manager.
+type PublisherSlot = tokio::sync::Mutex<Option<PublisherChannel>>;
 
+/// Publishes over a pool of confirm-mode channels, picked round-robin, so a
+/// channel error only takes down the publishes in flight on that channel
 #[derive(Clone)]
 pub struct AmqpPublisher {
     pub exchange: String,
-    pub channel: Arc<Channel>,
+    retry_policy: RetryPolicy,
+    confirm_timeout: Duration,
+    channels: Arc<Vec<PublisherSlot>>,
+    next_channel: Arc<AtomicUsize>,
+    // Organizations and notification types whose queues are declared again
+    // after a reconnection
+    topology: Arc<Mutex<QueueTopology>>,
 }
 
 impl AmqpPublisher {
-    pub async fn new(
-        host: &str,
-        port: u16,
-        username: &str,
-        password: &str,
+    pub fn new(
         exchange: &str,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let connection = match CONNECTION.get() {
-            Some(conn) => conn.clone(),
-            None => {
-                let connection = Arc::new(
-                    Connection::open(&OpenConnectionArguments::new(
-                        host, port, username, password,
-                    ))
-                    .await?,
-                );
-
-                connection
-                    .register_callback(DefaultConnectionCallback)
-                    .await?;
-                CONNECTION
-                    .set(connection.clone())
-                    .map_err(|_err| "Failed to set connection in the OnceCell".to_string())?;
-                connection
+        retry_policy: RetryPolicy,
+        confirm_timeout: Duration,
+        pool_size: usize,
+    ) -> Self {
+        let channels = (0..pool_size.max(1))
+            .map(|_| tokio::sync::Mutex::new(None))
+            .collect();
+
+        Self {
+            exchange: exchange.to_string(),
+            retry_policy,
+            confirm_timeout,
+            channels: Arc::new(channels),
+            next_channel: Arc::new(AtomicUsize::new(0)),
+            topology: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Declares the queues of every notification type of an organization,
+    /// along with their retry delay queues and dead-letter queue
+    pub async fn setup_queues(
+        &self,
+        org_id: &str,
+        notification_types: &[&str],
+    ) -> Result<(), AmqpError> {
+        let current = self.usable_channel().await?;
+        let publisher_channel = current.as_ref().expect("usable channels are open");
+
+        declare_queues(
+            &publisher_channel.channel,
+            &self.exchange,
+            org_id,
+            notification_types,
+            &self.retry_policy,
+        )
+        .await?;
+
+        self.topology.lock().unwrap().insert(
+            org_id.to_string(),
+            notification_types.iter().map(ToString::to_string).collect(),
+        );
+
+        Ok(())
+    }
+
+    /// Stops declaring the queues of an organization again after
+    /// reconnections. The queues themselves are kept, along with their
+    /// messages.
+    pub fn forget_queues(&self, org_id: &str) {
+        self.topology.lock().unwrap().remove(org_id);
+    }
+
+    /// Publishes a mandatory message and waits for the broker to confirm it
+    /// was routed to a queue
+    pub async fn publish(
+        &self,
+        routing_key: &str,
+        message_id: &str,
+        payload: Vec<u8>,
+    ) -> Result<(), PublishError> {
+        let properties = BasicProperties::default()
+            .with_message_id(message_id)
+            .finish();
+
+        self.publish_confirmed(&self.exchange, routing_key, properties, payload)
+            .await
+    }
+
+    /// Publishes a mandatory message straight to a queue, through the
+    /// default exchange, and waits for the broker to confirm it was enqueued
+    pub async fn publish_to_queue(
+        &self,
+        queue: &str,
+        properties: BasicProperties,
+        payload: Vec<u8>,
+    ) -> Result<(), PublishError> {
+        self.publish_confirmed("", queue, properties, payload).await
+    }
+
+    async fn publish_confirmed(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        properties: BasicProperties,
+        payload: Vec<u8>,
+    ) -> Result<(), PublishError> {
+        // Returned messages are matched by their message ID, so a message
+        // without one is only failed by a nack or a timeout
+        let message_id = properties.message_id().cloned().unwrap_or_default();
+        let message_id = message_id.as_str();
+
+        let (sender, receiver) = oneshot::channel();
+
+        let (delivery_tag, confirms) = {
+            // Delivery tags are assigned in publish order, so registering the
+            // publish and sending it must not interleave with other publishes
+            // on the same channel
+            let mut current = self
+                .usable_channel()
+                .await
+                .map_err(|err| PublishError::ChannelError(err.to_string()))?;
+            let publisher_channel = current.as_ref().expect("usable channels are open");
+
+            let confirms = Arc::clone(&publisher_channel.confirms);
+
+            let delivery_tag = confirms.lock().unwrap().register(PendingPublish {
+                message_id: message_id.to_string(),
+                routing_key: routing_key.to_string(),
+                sender,
+            });
+
+            let publish_args = BasicPublishArguments::new(exchange, routing_key)
+                .mandatory(true)
+                .finish();
+
+            if let Err(err) = publisher_channel
+                .channel
+                .basic_publish(properties, payload, publish_args)
+                .await
+            {
+                // Whether the broker counted the message is unknown, so the
+                // delivery tags of the channel can't be trusted anymore. The
+                // channel is dropped from the pool and reopened on recovery.
+                if let Some(poisoned) = current.take() {
+                    poisoned
+                        .confirms
+                        .lock()
+                        .unwrap()
+                        .fail_all("Publish failed on the channel");
+
+                    poisoned.notifier.notify();
+
+                    if poisoned.channel.is_open() {
+                        let _ = poisoned.channel.close().await;
+                    }
+                }
+
+                return Err(PublishError::ChannelError(err.to_string()));
             }
+
+            (delivery_tag, confirms)
         };
 
-        let channel = match CHANNEL.get() {
-            Some(ch) => ch.clone(),
-            None => {
-                let channel = Arc::new(connection.open_channel(None).await?);
-                channel.register_callback(DefaultChannelCallback).await?;
-                CHANNEL
-                    .set(channel.clone())
-                    .map_err(|_err| "Failed to set channel in the OnceCell".to_string())?;
+        match tokio::time::timeout(self.confirm_timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(PublishError::ChannelError(
+                "Confirmation was dropped".to_string(),
+            )),
+            Err(_) => {
+                let mut confirms = confirms.lock().unwrap();
+                confirms.pending.remove(&delivery_tag);
+                confirms.returned.remove(message_id);
 
-                channel
+                Err(PublishError::Timeout)
             }
-        };
+        }
+    }
+
+    /// Locks the next open channel of the pool, skipping channels waiting to
+    /// be recovered
+    async fn usable_channel(
+        &self,
+    ) -> Result<tokio::sync::MutexGuard<'_, Option<PublisherChannel>>, AmqpError> {
+        let start = self.next_channel.fetch_add(1, Ordering::Relaxed);
+
+        for offset in 0..self.channels.len() {
+            let slot = &self.channels[(start + offset) % self.channels.len()];
+            let current = slot.lock().await;
+
+            if current
+                .as_ref()
+                .is_some_and(|publisher_channel| publisher_channel.channel.is_open())
+            {
+                return Ok(current);
+            }
+        }
+
+        Err("No publisher channel is open".into())
+    }
+
+    async fn open_channel(
+        &self,
+        connection: &Connection,
+        notifier: &ChannelCloseNotifier,
+    ) -> Result<PublisherChannel, AmqpError> {
+        let channel = connection.open_channel(None).await?;
+        let confirms = Arc::new(Mutex::new(PendingConfirms::default()));
 
         channel
-            .exchange_declare(amqprs::channel::ExchangeDeclareArguments::new(
-                exchange, "topic",
-            ))
+            .register_callback(PublisherChannelCallback {
+                confirms: confirms.clone(),
+                notifier: notifier.clone(),
+            })
             .await?;
 
-        Ok(Self {
-            exchange: exchange.to_string(),
+        channel
+            .confirm_select(ConfirmSelectArguments::default())
+            .await?;
+
+        Ok(PublisherChannel {
             channel,
+            confirms,
+            generation: notifier.generation(),
+            notifier: notifier.clone(),
         })
     }
 
-    pub async fn setup_queues(
-        &self,
-        org_id: &str,
-        notification_types: &[&str],
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        for notification_type in notification_types {
-            let queue_name = format!("{}.{}", org_id, notification_type);
-            let routing_key = format!("{}.{}", org_id, notification_type);
+    async fn declare_topology(&self, channel: &Channel) -> Result<(), AmqpError> {
+        channel
+            .exchange_declare(ExchangeDeclareArguments::new(&self.exchange, "topic"))
+            .await?;
 
-            self.channel
-                .queue_declare(QueueDeclareArguments::new(&queue_name))
-                .await?;
+        let topology = self.topology.lock().unwrap().clone();
 
-            self.channel
-                .queue_bind(QueueBindArguments::new(
-                    &queue_name,
-                    &self.exchange,
-                    &routing_key,
-                ))
-                .await?;
+        for (org_id, notification_types) in &topology {
+            let notification_types: Vec<&str> =
+                notification_types.iter().map(String::as_str).collect();
+
+            declare_queues(
+                channel,
+                &self.exchange,
+                org_id,
+                &notification_types,
+                &self.retry_policy,
+            )
+            .await?;
         }
 
         Ok(())
     }
+}
 
-    pub async fn publish<T: Serialize>(
+#[async_trait]
+impl Recoverable for AmqpPublisher {
+    fn name(&self) -> String {
+        format!("publisher of exchange {}", self.exchange)
+    }
+
+    /// Reopens the channels of the pool that were closed or belong to a
+    /// previous connection, redeclaring the exchanges and queues on the way
+    async fn recover(
         &self,
-        routing_key: &str,
-        payload: &T,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let payload = serde_json::to_vec(payload)?;
-
-        self.channel
-            .basic_publish(
-                BasicProperties::default(),
-                payload,
-                BasicPublishArguments::new(&self.exchange, routing_key),
-            )
-            .await?;
+        connection: &Connection,
+        notifier: ChannelCloseNotifier,
+    ) -> Result<(), AmqpError> {
+        let mut declared = false;
+
+        for slot in self.channels.iter() {
+            let mut current = slot.lock().await;
+
+            let stale = match current.as_ref() {
+                Some(previous) => {
+                    previous.generation != notifier.generation() || !previous.channel.is_open()
+                }
+                None => true,
+            };
+
+            if !stale {
+                continue;
+            }
+
+            if let Some(previous) = current.take() {
+                previous
+                    .confirms
+                    .lock()
+                    .unwrap()
+                    .fail_all("Publisher channel was replaced");
+
+                if previous.channel.is_open() {
+                    let _ = previous.channel.close().await;
+                }
+            }
+
+            let publisher_channel = self.open_channel(connection, &notifier).await?;
+
+            if !declared {
+                self.declare_topology(&publisher_channel.channel).await?;
+                declared = true;
+            }
+
+            *current = Some(publisher_channel);
+        }
 
         Ok(())
     }
 }
 
-#[derive(Clone)]
+async fn declare_queues(
+    channel: &Channel,
+    exchange: &str,
+    org_id: &str,
+    notification_types: &[&str],
+    retry_policy: &RetryPolicy,
+) -> Result<(), AmqpError> {
+    for notification_type in notification_types {
+        let queue_name = format!("{}.{}", org_id, notification_type);
+        let routing_key = format!("{}.{}", org_id, notification_type);
+
+        // Declared without arguments, like before retries existed, so queues
+        // of existing deployments are redeclared as they are
+        channel
+            .queue_declare(QueueDeclareArguments::new(&queue_name))
+            .await?;
+
+        channel
+            .queue_bind(QueueBindArguments::new(&queue_name, exchange, &routing_key))
+            .await?;
+
+        channel
+            .queue_declare(QueueDeclareArguments::new(&RetryPolicy::dead_letter_queue(
+                &queue_name,
+            )))
+            .await?;
+
+        // Expired messages of a delay queue are dead-lettered back to the
+        // main exchange, which routes them to the original queue again
+        for attempt in 1..retry_policy.max_attempts {
+            let delay_ms = i32::try_from(retry_policy.delay_ms(attempt)).unwrap_or(i32::MAX);
+
+            let mut retry_arguments = FieldTable::new();
+            retry_arguments.insert(field_name("x-message-ttl"), FieldValue::I(delay_ms));
+            retry_arguments.insert(field_name("x-dead-letter-exchange"), exchange.into());
+            retry_arguments.insert(
+                field_name("x-dead-letter-routing-key"),
+                routing_key.as_str().into(),
+            );
+
+            channel
+                .queue_declare(
+                    QueueDeclareArguments::new(&retry_policy.retry_queue(&queue_name, attempt))
+                        .arguments(retry_arguments)
+                        .finish(),
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+type MessageHandler = Arc<
+    dyn Fn(
+            Deliver,
+            BasicProperties,
+            Vec<u8>,
+        ) -> Pin<Box<dyn Future<Output = Result<(), ConsumerError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+type FailureHandler = Arc<
+    dyn Fn(BasicProperties, FailureOutcome, String) -> Pin<Box<dyn Future<Output = ()> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// What became of a message its handler failed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureOutcome {
+    /// Published to the delay queue of its next attempt
+    Retrying,
+    /// Parked in the dead-letter queue
+    DeadLettered,
+    /// Nacked back onto its queue, since it couldn't be dead-lettered
+    Requeued,
+}
+
+/// Consumes a queue on a channel of its own, acking handled messages and
+/// sending failed ones to their retry or dead-letter queue. Failed messages
+/// are only acked once the broker confirms their retry or dead-letter copy.
 pub struct AmqpConsumer {
-    pub channel: Arc<Channel>,
     pub queue: String,
+    pub consumer_tag: String,
+    pub retry_policy: RetryPolicy,
+    /// How many unacknowledged messages the broker delivers to the consumer
+    pub prefetch_count: u16,
+    publisher: AmqpPublisher,
+    handler: MessageHandler,
+    on_failure: Option<FailureHandler>,
+    channel: tokio::sync::Mutex<Option<Channel>>,
 }
 
 impl AmqpConsumer {
-    pub async fn new(
-        host: &str,
-        port: u16,
-        username: &str,
-        password: &str,
+    pub fn new<F, Fut>(
         queue: &str,
-    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let connection = match CONNECTION.get() {
-            Some(conn) => conn.clone(),
-            None => {
-                let connection = Arc::new(
-                    Connection::open(&OpenConnectionArguments::new(
-                        host, port, username, password,
-                    ))
-                    .await?,
-                );
-
-                connection
-                    .register_callback(DefaultConnectionCallback)
-                    .await?;
-                CONNECTION
-                    .set(connection.clone())
-                    .map_err(|_err| "Failed to set connection in the OnceCell".to_string())?;
-                connection
+        consumer_tag: &str,
+        retry_policy: RetryPolicy,
+        prefetch_count: u16,
+        publisher: AmqpPublisher,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(Deliver, BasicProperties, Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), ConsumerError>> + Send + 'static,
+    {
+        Self {
+            queue: queue.to_string(),
+            consumer_tag: consumer_tag.to_string(),
+            retry_policy,
+            prefetch_count,
+            publisher,
+            handler: Arc::new(move |deliver, properties, content| {
+                Box::pin(handler(deliver, properties, content))
+            }),
+            on_failure: None,
+            channel: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Called with the outcome of every message the handler fails on, once
+    /// it has been retried, dead-lettered or requeued
+    pub fn on_failure<F, Fut>(mut self, on_failure: F) -> Self
+    where
+        F: Fn(BasicProperties, FailureOutcome, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_failure = Some(Arc::new(move |properties, outcome, error| {
+            Box::pin(on_failure(properties, outcome, error))
+        }));
+        self
+    }
+}
+
+#[async_trait]
+impl Recoverable for AmqpConsumer {
+    fn name(&self) -> String {
+        format!("consumer {}", self.consumer_tag)
+    }
+
+    async fn recover(
+        &self,
+        connection: &Connection,
+        notifier: ChannelCloseNotifier,
+    ) -> Result<(), AmqpError> {
+        let mut current = self.channel.lock().await;
+
+        if let Some(previous) = current.take() {
+            if previous.is_open() {
+                let _ = previous.close().await;
             }
+        }
+
+        // Kept right away, so a failure below still closes it on shutdown
+        let channel = current.insert(connection.open_channel(None).await?);
+
+        channel
+            .register_callback(RecoveryChannelCallback::new(notifier))
+            .await?;
+
+        channel
+            .basic_qos(BasicQosArguments::new(0, self.prefetch_count, false))
+            .await?;
+
+        let args = BasicConsumeArguments::new(&self.queue, &self.consumer_tag)
+            .manual_ack(true)
+            .finish();
+
+        let consumer = AsyncConsumer {
+            handler: Arc::clone(&self.handler),
+            on_failure: self.on_failure.clone(),
+            queue: self.queue.clone(),
+            retry_policy: self.retry_policy,
+            publisher: self.publisher.clone(),
         };
 
-        let channel = match CHANNEL.get() {
-            Some(ch) => ch.clone(),
-            None => {
-                let channel = Arc::new(connection.open_channel(None).await?);
-                channel.register_callback(DefaultChannelCallback).await?;
-                CHANNEL
-                    .set(channel.clone())
-                    .map_err(|_err| "Failed to set channel in the OnceCell".to_string())?;
+        channel.basic_consume(consumer, args).await?;
 
-                channel
+        Ok(())
+    }
+
+    async fn shutdown(&self) {
+        if let Some(channel) = self.channel.lock().await.take() {
+            if channel.is_open() {
+                let _ = channel.close().await;
             }
-        };
+        }
+    }
+}
 
-        Ok(Self {
-            channel,
-            queue: queue.to_string(),
-        })
+struct AsyncConsumer {
+    handler: MessageHandler,
+    on_failure: Option<FailureHandler>,
+    queue: String,
+    retry_policy: RetryPolicy,
+    publisher: AmqpPublisher,
+}
+
+impl AsyncConsumer {
+    /// Publishes a failed message to the delay queue of its next attempt.
+    /// Returns `false` when it has no attempts left.
+    async fn schedule_retry(
+        &self,
+        properties: &BasicProperties,
+        content: &[u8],
+    ) -> Result<bool, PublishError> {
+        if self.retry_policy.is_last_attempt(properties) {
+            return Ok(false);
+        }
+
+        let attempt = failed_attempts(properties) + 1;
+
+        let mut headers = properties.headers().cloned().unwrap_or_default();
+        headers.insert(field_name(ATTEMPT_HEADER), FieldValue::I(attempt as i32));
+
+        let mut retry_properties = properties.clone();
+        retry_properties.with_headers(headers);
+
+        let retry_queue = self.retry_policy.retry_queue(&self.queue, attempt);
+
+        self.publisher
+            .publish_to_queue(&retry_queue, retry_properties, content.to_vec())
+            .await?;
+
+        warn!(
+            "Message from {} scheduled for retry {} in {}ms",
+            self.queue,
+            attempt,
+            self.retry_policy.delay_ms(attempt)
+        );
+
+        Ok(true)
     }
 
-    pub async fn consume<F, Fut>(
+    /// Retries or dead-letters a failed message, leaving it to be requeued
+    /// when the broker confirmed neither
+    async fn dispose(
         &self,
-        consumer_tag: &str,
-        callback: F,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
-    where
-        F: Fn(Deliver, BasicProperties, Vec<u8>) -> Fut + Send + Sync + 'static,
-        Fut: std::future::Future<Output = Result<(), Box<dyn std::error::Error + Send>>>
-            + Send
-            + 'static,
-    {
-        let args = BasicConsumeArguments::new(&self.queue, consumer_tag)
-            .manual_ack(true)
-            .finish();
+        disposition: Disposition,
+        properties: &BasicProperties,
+        content: &[u8],
+    ) -> FailureOutcome {
+        if disposition == Disposition::Transient {
+            match self.schedule_retry(properties, content).await {
+                Ok(true) => return FailureOutcome::Retrying,
+                Ok(false) => {}
+                Err(err) => error!("Failed to schedule message retry: {:?}", err),
+            }
+        }
 
-        struct AsyncConsumer<F, Fut> {
-            callback: F,
-            _fut: std::marker::PhantomData<Fut>,
-        }
-
-        impl<F, Fut> AsyncConsumer<F, Fut>
-        where
-            F: Fn(Deliver, BasicProperties, Vec<u8>) -> Fut + Send + Sync + 'static,
-            Fut: std::future::Future<Output = Result<(), Box<dyn std::error::Error + Send>>>
-                + Send
-                + 'static,
-        {
-            fn new(callback: F) -> Self {
-                Self {
-                    callback,
-                    _fut: std::marker::PhantomData,
-                }
+        match self.dead_letter(properties, content).await {
+            Ok(()) => {
+                warn!("Message from {} moved to the dead-letter queue", self.queue);
+                FailureOutcome::DeadLettered
+            }
+            Err(err) => {
+                error!("Failed to dead-letter message: {:?}", err);
+                FailureOutcome::Requeued
             }
         }
+    }
 
-        #[async_trait]
-        impl<F, Fut> amqprs::consumer::AsyncConsumer for AsyncConsumer<F, Fut>
-        where
-            F: Fn(Deliver, BasicProperties, Vec<u8>) -> Fut + Send + Sync + 'static,
-            Fut: std::future::Future<Output = Result<(), Box<dyn std::error::Error + Send>>>
-                + Send
-                + 'static,
-        {
-            async fn consume(
-                &mut self,
-                channel: &Channel,
-                deliver: Deliver,
-                basic_properties: BasicProperties,
-                content: Vec<u8>,
-            ) {
-                let delivery_tag = deliver.delivery_tag();
-
-                if let Err(err) = (self.callback)(deliver, basic_properties, content).await {
-                    error!("Failed to consume message: {:?}", err);
-
-                    let nack_args = BasicNackArguments::new(delivery_tag, false, true);
-
-                    if let Err(err) = channel.basic_nack(nack_args).await {
-                        error!("Failed to nack message: {:?}", err);
-                    }
-                } else {
-                    let ack_args = amqprs::channel::BasicAckArguments::new(delivery_tag, false);
+    /// Parks a message that won't be retried in the `{queue}.dlq` queue
+    async fn dead_letter(
+        &self,
+        properties: &BasicProperties,
+        content: &[u8],
+    ) -> Result<(), PublishError> {
+        let dead_letter_queue = RetryPolicy::dead_letter_queue(&self.queue);
 
-                    if let Err(err) = channel.basic_ack(ack_args).await {
-                        error!("Failed to ack message: {:?}", err);
-                    }
+        self.publisher
+            .publish_to_queue(&dead_letter_queue, properties.clone(), content.to_vec())
+            .await
+    }
+}
+
+#[async_trait]
+impl amqprs::consumer::AsyncConsumer for AsyncConsumer {
+    async fn consume(
+        &mut self,
+        channel: &Channel,
+        deliver: Deliver,
+        basic_properties: BasicProperties,
+        content: Vec<u8>,
+    ) {
+        let delivery_tag = deliver.delivery_tag();
+        let properties = basic_properties.clone();
+        let failed_content = content.clone();
+
+        if let Err(err) = (self.handler)(deliver, basic_properties, content).await {
+            let disposition = err.disposition();
+
+            error!("Failed to consume message ({:?}): {:?}", disposition, err);
+
+            let outcome = self
+                .dispose(disposition, &properties, &failed_content)
+                .await;
+
+            if let Some(on_failure) = &self.on_failure {
+                on_failure(properties, outcome, err.to_string()).await;
+            }
+
+            if outcome == FailureOutcome::Requeued {
+                // Requeued rather than lost, to be dead-lettered on its next
+                // delivery
+                let nack_args = BasicNackArguments::new(delivery_tag, false, true);
+
+                if let Err(err) = channel.basic_nack(nack_args).await {
+                    error!("Failed to nack message: {:?}", err);
                 }
+
+                return;
             }
         }
 
-        let consumer = AsyncConsumer::new(callback);
+        let ack_args = amqprs::channel::BasicAckArguments::new(delivery_tag, false);
 
-        self.channel.basic_consume(consumer, args).await?;
+        if let Err(err) = channel.basic_ack(ack_args).await {
+            error!("Failed to ack message: {:?}", err);
+        }
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_attempt(value: FieldValue) -> BasicProperties {
+        let mut headers = FieldTable::new();
+        headers.insert(field_name(ATTEMPT_HEADER), value);
+
+        let mut properties = BasicProperties::default();
+        properties.with_headers(headers);
+        properties
+    }
+
+    fn register(
+        confirms: &mut PendingConfirms,
+        message_id: &str,
+    ) -> oneshot::Receiver<Result<(), PublishError>> {
+        let (sender, receiver) = oneshot::channel();
+
+        confirms.register(PendingPublish {
+            message_id: message_id.to_string(),
+            routing_key: "acme.email".to_string(),
+            sender,
+        });
+
+        receiver
+    }
+
+    #[test]
+    fn a_multiple_ack_confirms_every_publish_up_to_its_tag() {
+        let mut confirms = PendingConfirms::default();
+        let mut first = register(&mut confirms, "n1");
+        let mut second = register(&mut confirms, "n2");
+        let mut third = register(&mut confirms, "n3");
+
+        confirms.ack(2, true);
+
+        assert!(matches!(first.try_recv(), Ok(Ok(()))));
+        assert!(matches!(second.try_recv(), Ok(Ok(()))));
+        assert!(third.try_recv().is_err());
+        assert_eq!(confirms.pending.keys().copied().collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn a_single_ack_confirms_only_its_tag() {
+        let mut confirms = PendingConfirms::default();
+        let mut first = register(&mut confirms, "n1");
+        let mut second = register(&mut confirms, "n2");
+
+        confirms.ack(2, false);
+
+        assert!(first.try_recv().is_err());
+        assert!(matches!(second.try_recv(), Ok(Ok(()))));
+    }
+
+    #[test]
+    fn a_multiple_nack_fails_every_publish_up_to_its_tag() {
+        let mut confirms = PendingConfirms::default();
+        let mut first = register(&mut confirms, "n1");
+        let mut second = register(&mut confirms, "n2");
+        let mut third = register(&mut confirms, "n3");
+
+        confirms.nack(2, true);
+        confirms.ack(3, false);
+
+        assert!(matches!(first.try_recv(), Ok(Err(PublishError::Nacked))));
+        assert!(matches!(second.try_recv(), Ok(Err(PublishError::Nacked))));
+        assert!(matches!(third.try_recv(), Ok(Ok(()))));
+    }
+
+    #[test]
+    fn a_returned_publish_fails_once_acked() {
+        let mut confirms = PendingConfirms::default();
+        let mut returned = register(&mut confirms, "n1");
+        let mut routed = register(&mut confirms, "n2");
+
+        confirms.returned.insert("n1".to_string());
+        confirms.ack(2, true);
+
+        assert!(matches!(
+            returned.try_recv(),
+            Ok(Err(PublishError::Unroutable(routing_key))) if routing_key == "acme.email"
+        ));
+        assert!(matches!(routed.try_recv(), Ok(Ok(()))));
+        assert!(confirms.returned.is_empty());
+    }
+
+    #[test]
+    fn retry_delays_grow_exponentially() {
+        let policy = RetryPolicy::new(5, 1000);
+
+        assert_eq!(policy.delay_ms(1), 1000);
+        assert_eq!(policy.delay_ms(2), 2000);
+        assert_eq!(policy.delay_ms(3), 4000);
+        assert_eq!(policy.delay_ms(4), 8000);
+        assert_eq!(
+            policy.retry_queue("acme.email", 3),
+            "acme.email.retry.4000ms"
+        );
+    }
+
+    #[test]
+    fn retry_delays_are_capped() {
+        let policy = RetryPolicy::new(100, 1000);
+
+        assert_eq!(policy.delay_ms(32), 1000 << 31);
+        assert_eq!(policy.delay_ms(33), 1000 << 31);
+        assert_eq!(policy.delay_ms(u32::MAX), 1000 << 31);
+        assert_eq!(RetryPolicy::new(100, u64::MAX).delay_ms(10), u64::MAX);
+    }
+
+    #[test]
+    fn the_last_attempt_is_reached_at_the_attempt_limit() {
+        let policy = RetryPolicy::new(3, 1000);
+
+        assert!(!policy.is_last_attempt(&BasicProperties::default()));
+        assert!(!policy.is_last_attempt(&with_attempt(FieldValue::I(1))));
+        assert!(policy.is_last_attempt(&with_attempt(FieldValue::I(2))));
+        assert!(policy.is_last_attempt(&with_attempt(FieldValue::I(7))));
+    }
+
+    #[test]
+    fn a_single_attempt_is_always_the_last() {
+        assert!(RetryPolicy::new(1, 1000).is_last_attempt(&BasicProperties::default()));
+        assert!(RetryPolicy::new(0, 1000).is_last_attempt(&BasicProperties::default()));
+    }
+
+    #[test]
+    fn failed_attempts_are_read_from_the_header() {
+        assert_eq!(failed_attempts(&with_attempt(FieldValue::I(2))), 2);
+        assert_eq!(failed_attempts(&with_attempt(FieldValue::i(3))), 3);
+        assert_eq!(failed_attempts(&with_attempt(FieldValue::l(4))), 4);
+    }
+
+    #[test]
+    fn missing_or_malformed_attempt_headers_count_as_no_failures() {
+        assert_eq!(failed_attempts(&BasicProperties::default()), 0);
+        assert_eq!(failed_attempts(&with_attempt(FieldValue::I(-1))), 0);
+        assert_eq!(failed_attempts(&with_attempt(FieldValue::l(-5))), 0);
+        assert_eq!(
+            failed_attempts(&with_attempt(FieldValue::S("2".try_into().unwrap()))),
+            0
+        );
+        assert_eq!(
+            failed_attempts(&with_attempt(FieldValue::l(i64::MAX))),
+            u32::MAX
+        );
     }
 }
@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex, RwLock,
+};
+use std::time::Duration;
+
+use amqprs::{
+    callbacks::{ChannelCallback, ConnectionCallback},
+    channel::Channel,
+    connection::{Connection, OpenConnectionArguments},
+    Ack, BasicProperties, Cancel, Close, CloseChannel, Nack, Return,
+};
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::tracing::{error, info, warn};
+
+pub type AmqpError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Identifies a component registered with the connection manager
+pub type ComponentId = usize;
+
+/// How often the supervisor checks the connection and retries components
+/// that failed to recover
+const SUPERVISOR_TICK: Duration = Duration::from_secs(5);
+
+/// A publisher or consumer that owns AMQP channels and can rebuild them on a
+/// new connection
+#[async_trait]
+pub trait Recoverable: Send + Sync {
+    fn name(&self) -> String;
+
+    /// Opens the channels of the component and declares its AMQP state. Runs
+    /// on registration and again after every reconnection.
+    async fn recover(
+        &self,
+        connection: &Connection,
+        notifier: ChannelCloseNotifier,
+    ) -> Result<(), AmqpError>;
+
+    /// Closes the channels of the component once it is unregistered
+    async fn shutdown(&self) {}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    pub fn new(base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            base_delay,
+            max_delay: max_delay.max(base_delay),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum RecoveryEvent {
+    ConnectionClosed { generation: u64 },
+    ChannelClosed { component: usize, generation: u64 },
+}
+
+/// Lets a component report that the server closed one of its channels
+#[derive(Clone)]
+pub struct ChannelCloseNotifier {
+    component: usize,
+    generation: u64,
+    events: UnboundedSender<RecoveryEvent>,
+}
+
+impl ChannelCloseNotifier {
+    /// Generation of the connection the component is being recovered on,
+    /// increased on every reconnection
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn notify(&self) {
+        let _ = self.events.send(RecoveryEvent::ChannelClosed {
+            component: self.component,
+            generation: self.generation,
+        });
+    }
+}
+
+/// Channel callback of components with no other asynchronous frames to
+/// handle, reporting server-initiated channel closes
+pub struct RecoveryChannelCallback {
+    notifier: ChannelCloseNotifier,
+}
+
+impl RecoveryChannelCallback {
+    pub fn new(notifier: ChannelCloseNotifier) -> Self {
+        Self { notifier }
+    }
+}
+
+#[async_trait]
+impl ChannelCallback for RecoveryChannelCallback {
+    async fn close(
+        &mut self,
+        channel: &Channel,
+        close: CloseChannel,
+    ) -> Result<(), amqprs::error::Error> {
+        error!("Channel {} closed by the server: {}", channel, close);
+        self.notifier.notify();
+        Ok(())
+    }
+
+    async fn cancel(
+        &mut self,
+        channel: &Channel,
+        cancel: Cancel,
+    ) -> Result<(), amqprs::error::Error> {
+        warn!(
+            "Consumer {} cancelled by the server on channel {}",
+            cancel.consumer_tag(),
+            channel
+        );
+        self.notifier.notify();
+        Ok(())
+    }
+
+    async fn flow(
+        &mut self,
+        _channel: &Channel,
+        active: bool,
+    ) -> Result<bool, amqprs::error::Error> {
+        Ok(active)
+    }
+
+    async fn publish_ack(&mut self, _channel: &Channel, _ack: Ack) {}
+
+    async fn publish_nack(&mut self, _channel: &Channel, _nack: Nack) {}
+
+    async fn publish_return(
+        &mut self,
+        _channel: &Channel,
+        _ret: Return,
+        _basic_properties: BasicProperties,
+        _content: Vec<u8>,
+    ) {
+    }
+}
+
+struct ManagedConnectionCallback {
+    generation: u64,
+    events: UnboundedSender<RecoveryEvent>,
+}
+
+#[async_trait]
+impl ConnectionCallback for ManagedConnectionCallback {
+    async fn close(
+        &mut self,
+        connection: &Connection,
+        close: Close,
+    ) -> Result<(), amqprs::error::Error> {
+        error!("Connection {} closed by the server: {}", connection, close);
+
+        let _ = self.events.send(RecoveryEvent::ConnectionClosed {
+            generation: self.generation,
+        });
+
+        Ok(())
+    }
+
+    async fn blocked(&mut self, connection: &Connection, reason: String) {
+        warn!(
+            "Connection {} blocked by the server: {}",
+            connection, reason
+        );
+    }
+
+    async fn unblocked(&mut self, connection: &Connection) {
+        info!("Connection {} unblocked by the server", connection);
+    }
+}
+
+/// Owns the AMQP connection, reconnecting with backoff when it is lost and
+/// recovering every registered component, in registration order, on the new
+/// connection
+pub struct ConnectionManager {
+    arguments: OpenConnectionArguments,
+    reconnect_policy: ReconnectPolicy,
+    connection: RwLock<Option<Arc<Connection>>>,
+    generation: AtomicU64,
+    connected: AtomicBool,
+    components: tokio::sync::Mutex<BTreeMap<ComponentId, Arc<dyn Recoverable>>>,
+    next_component: AtomicUsize,
+    failed: Mutex<HashSet<ComponentId>>,
+    events: UnboundedSender<RecoveryEvent>,
+}
+
+impl ConnectionManager {
+    pub async fn connect(
+        arguments: OpenConnectionArguments,
+        reconnect_policy: ReconnectPolicy,
+    ) -> Result<Arc<Self>, AmqpError> {
+        let (events, receiver) = mpsc::unbounded_channel();
+
+        let manager = Arc::new(Self {
+            arguments,
+            reconnect_policy,
+            connection: RwLock::new(None),
+            generation: AtomicU64::new(0),
+            connected: AtomicBool::new(false),
+            components: tokio::sync::Mutex::new(BTreeMap::new()),
+            next_component: AtomicUsize::new(0),
+            failed: Mutex::new(HashSet::new()),
+            events,
+        });
+
+        let connection = manager.open_connection(0).await?;
+        *manager.connection.write().unwrap() = Some(connection);
+        manager.connected.store(true, Ordering::SeqCst);
+
+        tokio::spawn(Arc::clone(&manager).supervise(receiver));
+
+        Ok(manager)
+    }
+
+    /// Whether the connection is up and every component is recovered
+    pub fn is_healthy(&self) -> bool {
+        self.connected.load(Ordering::SeqCst) && self.failed.lock().unwrap().is_empty()
+    }
+
+    /// Opens the channels of the component and keeps it recovered across
+    /// reconnections
+    pub async fn register(
+        &self,
+        component: Arc<dyn Recoverable>,
+    ) -> Result<ComponentId, AmqpError> {
+        let mut components = self.components.lock().await;
+
+        let connection = self
+            .current_connection()
+            .ok_or("The AMQP connection is not open")?;
+
+        let id = self.next_component.fetch_add(1, Ordering::SeqCst);
+
+        if let Err(err) = component.recover(&connection, self.notifier(id)).await {
+            error!("Failed to register {}: {}", component.name(), err);
+
+            // Channels opened before the failure must not keep consuming
+            component.shutdown().await;
+
+            return Err(err);
+        }
+
+        components.insert(id, component);
+
+        Ok(id)
+    }
+
+    /// Stops recovering the component and closes its channels
+    pub async fn unregister(&self, id: ComponentId) {
+        let component = self.components.lock().await.remove(&id);
+
+        self.failed.lock().unwrap().remove(&id);
+
+        if let Some(component) = component {
+            component.shutdown().await;
+
+            info!("Unregistered {}", component.name());
+        }
+    }
+
+    fn current_connection(&self) -> Option<Arc<Connection>> {
+        self.connection.read().unwrap().clone()
+    }
+
+    fn notifier(&self, component: ComponentId) -> ChannelCloseNotifier {
+        ChannelCloseNotifier {
+            component,
+            generation: self.generation.load(Ordering::SeqCst),
+            events: self.events.clone(),
+        }
+    }
+
+    async fn open_connection(&self, generation: u64) -> Result<Arc<Connection>, AmqpError> {
+        let connection = Connection::open(&self.arguments).await?;
+
+        connection
+            .register_callback(ManagedConnectionCallback {
+                generation,
+                events: self.events.clone(),
+            })
+            .await?;
+
+        Ok(Arc::new(connection))
+    }
+
+    async fn supervise(self: Arc<Self>, mut events: UnboundedReceiver<RecoveryEvent>) {
+        let mut ticker = tokio::time::interval(SUPERVISOR_TICK);
+
+        loop {
+            let connection = self.current_connection();
+
+            let network_failure = async {
+                match &connection {
+                    Some(connection) => {
+                        connection.listen_network_io_failure().await;
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = network_failure => {
+                    warn!("AMQP connection lost");
+                    self.reconnect().await;
+                }
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        return;
+                    };
+
+                    let generation = self.generation.load(Ordering::SeqCst);
+
+                    match event {
+                        RecoveryEvent::ConnectionClosed { generation: closed } if closed == generation => {
+                            self.reconnect().await;
+                        }
+                        // Recovered by the next tick rather than right away, so a
+                        // channel the server keeps closing isn't reopened in a
+                        // tight loop
+                        RecoveryEvent::ChannelClosed { component, generation: closed } if closed == generation => {
+                            self.failed.lock().unwrap().insert(component);
+                        }
+                        // Events of a previous connection are handled by the
+                        // full recovery that followed it
+                        _ => {}
+                    }
+                }
+                _ = ticker.tick() => {
+                    let is_open = connection.as_ref().is_some_and(|connection| connection.is_open());
+
+                    if !is_open {
+                        self.reconnect().await;
+                    } else {
+                        self.recover_failed().await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn reconnect(&self) {
+        self.connected.store(false, Ordering::SeqCst);
+
+        // Registrations fail fast while the broker is unreachable, instead of
+        // waiting for the retry loop below
+        *self.connection.write().unwrap() = None;
+
+        let mut delay = self.reconnect_policy.base_delay;
+
+        let generation = self.generation.load(Ordering::SeqCst) + 1;
+
+        let connection = loop {
+            match self.open_connection(generation).await {
+                Ok(connection) => break connection,
+                Err(err) => {
+                    warn!(
+                        "Failed to reconnect to RabbitMQ, retrying in {}ms: {}",
+                        delay.as_millis(),
+                        err
+                    );
+
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(self.reconnect_policy.max_delay);
+                }
+            }
+        };
+
+        let components = self.components.lock().await;
+
+        *self.connection.write().unwrap() = Some(Arc::clone(&connection));
+        self.generation.store(generation, Ordering::SeqCst);
+
+        info!(
+            "Reconnected to RabbitMQ, recovering {} components",
+            components.len()
+        );
+
+        for (id, component) in components.iter() {
+            self.recover(*id, component.as_ref(), &connection).await;
+        }
+
+        self.connected.store(true, Ordering::SeqCst);
+    }
+
+    async fn recover_component(&self, id: ComponentId) {
+        let components = self.components.lock().await;
+
+        let (Some(component), Some(connection)) = (components.get(&id), self.current_connection())
+        else {
+            return;
+        };
+
+        self.recover(id, component.as_ref(), &connection).await;
+    }
+
+    async fn recover_failed(&self) {
+        let failed: Vec<ComponentId> = self.failed.lock().unwrap().iter().copied().collect();
+
+        for id in failed {
+            self.recover_component(id).await;
+        }
+    }
+
+    async fn recover(&self, id: ComponentId, component: &dyn Recoverable, connection: &Connection) {
+        match component.recover(connection, self.notifier(id)).await {
+            Ok(()) => {
+                self.failed.lock().unwrap().remove(&id);
+                info!("Recovered {}", component.name());
+            }
+            Err(err) => {
+                self.failed.lock().unwrap().insert(id);
+                error!("Failed to recover {}: {}", component.name(), err);
+            }
+        }
+    }
+}
@@ -1,13 +1,33 @@
+use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
 
-use crate::infra::amqp::AmqpConsumer;
-use crate::tracing::{error, info};
+use crate::config::Config;
+use crate::domain::notification::{NotificationChannel, NotificationStatus};
+use crate::infra::amqp::{AmqpConsumer, AmqpPublisher, FailureOutcome, RetryPolicy};
+use crate::infra::connection::{AmqpError, ComponentId, ConnectionManager};
+use crate::providers::email::EmailSender;
+use crate::providers::push::{fcm::FcmPushProvider, PushProviderError};
+use crate::providers::sms::{twilio::TwilioSmsProvider, SmsProviderError};
+use crate::status::store::{NotificationStatusStore, StatusUpdate};
+use crate::templates::email::{cache::EmailTemplateCache, template::TemplateError};
+use crate::tracing::{error, info, warn};
 use crate::workers::email::EmailWorker;
+use crate::workers::push::PushWorker;
+use crate::workers::sms::SmsWorker;
 
-use amqprs::BasicProperties;
-use amqprs::Deliver;
+use amqprs::{BasicProperties, Deliver};
 use thiserror::Error;
 
+/// Whether a failed message is worth delivering again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    /// The failure may go away on its own, like a provider outage or timeout
+    Transient,
+    /// The message will never succeed, like bad JSON or a missing template
+    Permanent,
+}
+
 #[derive(Error, Debug)]
 pub enum ConsumerError {
     #[error("Failed to decode notification")]
@@ -15,60 +35,435 @@ pub enum ConsumerError {
 
     #[error("Failed to parse notification")]
     ParseError,
+
+    #[error("Failed to prepare notification template: {0}")]
+    TemplateError(#[from] TemplateError),
+
+    #[error("Failed to deliver notification: {message}")]
+    DeliveryError {
+        message: String,
+        disposition: Disposition,
+    },
+}
+
+impl ConsumerError {
+    pub fn disposition(&self) -> Disposition {
+        match self {
+            ConsumerError::DecodeError | ConsumerError::ParseError => Disposition::Permanent,
+            ConsumerError::TemplateError(err) => match err {
+                TemplateError::NotFound(_)
+                | TemplateError::InvalidId(_)
+                | TemplateError::InvalidLocale(_)
+                | TemplateError::InvalidTemplate { .. }
+                | TemplateError::ValidationFailed(_)
+                | TemplateError::BrokenPartials(_)
+                | TemplateError::MissingVariable { .. }
+                | TemplateError::RenderError(_) => Disposition::Permanent,
+                TemplateError::StoreError(_) => Disposition::Transient,
+            },
+            ConsumerError::DeliveryError { disposition, .. } => *disposition,
+        }
+    }
+}
+
+impl From<SmsProviderError> for ConsumerError {
+    fn from(err: SmsProviderError) -> Self {
+        let disposition = if err.is_transient() {
+            Disposition::Transient
+        } else {
+            Disposition::Permanent
+        };
+
+        ConsumerError::DeliveryError {
+            message: err.to_string(),
+            disposition,
+        }
+    }
 }
 
-pub async fn handle_sms_notification(
-    _deliver: Deliver,
-    _properties: BasicProperties,
-    content: Vec<u8>,
-) -> Result<(), Box<dyn std::error::Error + Send>> {
-    info!(
-        "Handling SMS notification: {:?}",
-        String::from_utf8(content)
-    );
-    // Process SMS notification
-    Ok(())
+impl From<PushProviderError> for ConsumerError {
+    fn from(err: PushProviderError) -> Self {
+        let disposition = if err.is_transient() {
+            Disposition::Transient
+        } else {
+            Disposition::Permanent
+        };
+
+        ConsumerError::DeliveryError {
+            message: err.to_string(),
+            disposition,
+        }
+    }
 }
 
-pub async fn handle_push_notification(
-    _deliver: Deliver,
-    _properties: BasicProperties,
-    content: Vec<u8>,
-) -> Result<(), Box<dyn std::error::Error + Send>> {
-    info!(
-        "Handling push notification: {:?}",
-        String::from_utf8(content)
-    );
-    // Process push notification
-    Ok(())
+/// Records the lifecycle of the notifications handled by a worker, using the
+/// AMQP message ID as the notification ID
+#[derive(Clone)]
+struct StatusTracker {
+    statuses: Arc<dyn NotificationStatusStore>,
 }
 
-pub async fn start_consumers(
-    config: &crate::config::Config,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let email_consumer = AmqpConsumer::new(
-        &config.rabbitmq_host,
-        config.rabbitmq_port,
-        &config.rabbitmq_user,
-        &config.rabbitmq_password,
-        "organization-1.email",
-    )
-    .await?;
-
-    tokio::spawn(async move {
-        let worker = Arc::new(EmailWorker::new());
-
-        let consumer = email_consumer
-            .consume("email_consumer", move |d, p, c| {
+impl StatusTracker {
+    async fn track<F>(&self, properties: &BasicProperties, delivery: F) -> Result<(), ConsumerError>
+    where
+        F: Future<Output = Result<String, ConsumerError>>,
+    {
+        let Some(id) = properties.message_id() else {
+            return delivery.await.map(|_message_id| ());
+        };
+
+        // The broker redelivers messages whose ack was lost, which must not
+        // reach the recipient twice
+        if self.already_sent(id).await {
+            info!("Notification {} was already sent, skipping redelivery", id);
+
+            return Ok(());
+        }
+
+        self.record(id, NotificationStatus::Processing, StatusUpdate::default())
+            .await;
+
+        // Failures are recorded by `record_failure`, once the consumer has
+        // decided what becomes of the message
+        let message_id = delivery.await?;
+
+        self.record(id, NotificationStatus::Sent, StatusUpdate::sent(message_id))
+            .await;
+
+        Ok(())
+    }
+
+    async fn record_failure(
+        &self,
+        properties: &BasicProperties,
+        outcome: FailureOutcome,
+        error: String,
+    ) {
+        let Some(id) = properties.message_id() else {
+            return;
+        };
+
+        // A requeued message is delivered again just like a retried one
+        let status = match outcome {
+            FailureOutcome::DeadLettered => NotificationStatus::DeadLettered,
+            FailureOutcome::Retrying | FailureOutcome::Requeued => NotificationStatus::Retrying,
+        };
+
+        self.record(id, status, StatusUpdate::failed(error)).await;
+    }
+
+    async fn already_sent(&self, id: &str) -> bool {
+        match self.statuses.find_by_id(id).await {
+            Ok(record) => record.is_some_and(|record| record.status == NotificationStatus::Sent),
+            Err(err) => {
+                warn!("Failed to look up notification {}: {:?}", id, err);
+                false
+            }
+        }
+    }
+
+    async fn record(&self, id: &str, status: NotificationStatus, update: StatusUpdate) {
+        if let Err(err) = self.statuses.transition(id, status, update).await {
+            warn!(
+                "Failed to record notification {} as {:?}: {:?}",
+                id, status, err
+            );
+        }
+    }
+}
+
+/// Starts the notification consumers of an organization, sharing the same
+/// workers between every organization
+pub struct Consumers {
+    config: &'static Config,
+    connection: Arc<ConnectionManager>,
+    retry_policy: RetryPolicy,
+    // Publishes failed messages to their retry or dead-letter queue
+    publisher: AmqpPublisher,
+    tracker: StatusTracker,
+    email_worker: Arc<EmailWorker>,
+    sms_worker: Option<Arc<SmsWorker>>,
+    push_worker: Option<Arc<PushWorker>>,
+    // Consumers of every started organization
+    started: tokio::sync::Mutex<HashMap<String, Vec<ComponentId>>>,
+}
+
+impl Consumers {
+    pub fn new(
+        config: &'static Config,
+        connection: Arc<ConnectionManager>,
+        retry_policy: RetryPolicy,
+        publisher: AmqpPublisher,
+        statuses: Arc<dyn NotificationStatusStore>,
+        email_templates: Arc<EmailTemplateCache>,
+        email_sender: Arc<EmailSender>,
+    ) -> Result<Self, PushProviderError> {
+        let sms_provider = match (
+            &config.twilio_account_sid,
+            &config.twilio_auth_token,
+            &config.twilio_from_number,
+        ) {
+            (Some(account_sid), Some(auth_token), Some(from_number)) => {
+                Some(Arc::new(TwilioSmsProvider::new(
+                    account_sid.clone(),
+                    auth_token.clone(),
+                    from_number.clone(),
+                )))
+            }
+            _ => {
+                warn!("Twilio is not configured, SMS notifications are disabled");
+                None
+            }
+        };
+
+        // A configured but unreadable service account is an error, not a
+        // reason to silently disable the channel
+        let push_provider = match &config.fcm_service_account_path {
+            Some(path) => Some(Arc::new(FcmPushProvider::from_service_account_file(path)?)),
+            None => {
+                warn!("FCM is not configured, push notifications are disabled");
+                None
+            }
+        };
+
+        Ok(Self {
+            config,
+            connection,
+            retry_policy,
+            publisher,
+            tracker: StatusTracker { statuses },
+            email_worker: Arc::new(EmailWorker::new(email_templates, email_sender)),
+            sms_worker: sms_provider.map(|provider| {
+                Arc::new(SmsWorker::new(provider, config.sms_templates_path.clone()))
+            }),
+            push_worker: push_provider.map(|provider| Arc::new(PushWorker::new(provider))),
+            started: tokio::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether the channel's provider is configured
+    pub fn supports(&self, channel: NotificationChannel) -> bool {
+        match channel {
+            NotificationChannel::Email => true,
+            NotificationChannel::Sms => self.sms_worker.is_some(),
+            NotificationChannel::Push => self.push_worker.is_some(),
+        }
+    }
+
+    /// Starts the consumers of every channel of the organization. Consumers
+    /// started before a failure are stopped again, so the start can be
+    /// retried.
+    pub async fn start(&self, org_id: &str) -> Result<(), AmqpError> {
+        let mut started = Vec::new();
+
+        if let Err(err) = self.start_consumers(org_id, &mut started).await {
+            for id in started {
+                self.connection.unregister(id).await;
+            }
+
+            return Err(err);
+        }
+
+        self.started
+            .lock()
+            .await
+            .insert(org_id.to_string(), started);
+
+        info!("Consumers for organization {} started", org_id);
+
+        Ok(())
+    }
+
+    /// Stops the consumers of the organization
+    pub async fn stop(&self, org_id: &str) {
+        let started = self.started.lock().await.remove(org_id);
+
+        for id in started.unwrap_or_default() {
+            self.connection.unregister(id).await;
+        }
+    }
+
+    async fn start_consumers(
+        &self,
+        org_id: &str,
+        started: &mut Vec<ComponentId>,
+    ) -> Result<(), AmqpError> {
+        let worker = Arc::clone(&self.email_worker);
+        let tracker = self.tracker.clone();
+
+        let prefetch_count = self.config.email_consumer_prefetch;
+
+        let id = self
+            .register(org_id, "email", prefetch_count, move |d, p, c| {
                 let worker = Arc::clone(&worker);
-                async move { worker.handle(d, p, c).await }
+                let tracker = tracker.clone();
+
+                async move {
+                    let properties = p.clone();
+                    tracker.track(&properties, worker.handle(d, p, c)).await
+                }
             })
-            .await;
+            .await?;
+
+        started.push(id);
+
+        if let Some(worker) = &self.sms_worker {
+            let worker = Arc::clone(worker);
+            let tracker = self.tracker.clone();
+
+            let prefetch_count = self.config.sms_consumer_prefetch;
+
+            let id = self
+                .register(org_id, "sms", prefetch_count, move |d, p, c| {
+                    let worker = Arc::clone(&worker);
+                    let tracker = tracker.clone();
+
+                    async move {
+                        let properties = p.clone();
+                        tracker.track(&properties, worker.handle(d, p, c)).await
+                    }
+                })
+                .await?;
+
+            started.push(id);
+        }
+
+        if let Some(worker) = &self.push_worker {
+            let worker = Arc::clone(worker);
+            let tracker = self.tracker.clone();
+
+            let prefetch_count = self.config.push_consumer_prefetch;
+
+            let id = self
+                .register(org_id, "push", prefetch_count, move |d, p, c| {
+                    let worker = Arc::clone(&worker);
+                    let tracker = tracker.clone();
+
+                    async move {
+                        let properties = p.clone();
+                        tracker.track(&properties, worker.handle(d, p, c)).await
+                    }
+                })
+                .await?;
 
-        if let Err(err) = consumer {
-            error!("Failed to start email consumer: {:?}", err);
+            started.push(id);
         }
-    });
 
-    Ok(())
+        Ok(())
+    }
+
+    /// Starts consuming the queue of a notification type, on a channel the
+    /// connection manager recovers after reconnections
+    async fn register<F, Fut>(
+        &self,
+        org_id: &str,
+        notification_type: &str,
+        prefetch_count: u16,
+        handler: F,
+    ) -> Result<ComponentId, AmqpError>
+    where
+        F: Fn(Deliver, BasicProperties, Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), ConsumerError>> + Send + 'static,
+    {
+        let consumer = AmqpConsumer::new(
+            &format!("{}.{}", org_id, notification_type),
+            &format!("{}.{}_consumer", org_id, notification_type),
+            self.retry_policy,
+            prefetch_count,
+            self.publisher.clone(),
+            handler,
+        )
+        .on_failure({
+            let tracker = self.tracker.clone();
+
+            move |properties, outcome, error| {
+                let tracker = tracker.clone();
+
+                async move { tracker.record_failure(&properties, outcome, error).await }
+            }
+        });
+
+        self.connection
+            .register(Arc::new(consumer))
+            .await
+            .map_err(|err| {
+                error!(
+                    "Failed to start {} consumer of organization {}: {}",
+                    notification_type, org_id, err
+                );
+                err
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected_sms(status: u16) -> ConsumerError {
+        SmsProviderError::Rejected {
+            status,
+            message: "rejected".to_string(),
+        }
+        .into()
+    }
+
+    fn rejected_push(status: u16) -> ConsumerError {
+        PushProviderError::Rejected {
+            status,
+            message: "rejected".to_string(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn errors_map_to_their_disposition() {
+        let cases = [
+            (ConsumerError::DecodeError, Disposition::Permanent),
+            (ConsumerError::ParseError, Disposition::Permanent),
+            (
+                TemplateError::NotFound("welcome".to_string()).into(),
+                Disposition::Permanent,
+            ),
+            (
+                TemplateError::MissingVariable {
+                    name: "name".to_string(),
+                    template_id: "welcome".to_string(),
+                }
+                .into(),
+                Disposition::Permanent,
+            ),
+            (
+                TemplateError::StoreError("database is locked".to_string()).into(),
+                Disposition::Transient,
+            ),
+            (rejected_sms(429), Disposition::Transient),
+            (rejected_sms(503), Disposition::Transient),
+            (rejected_sms(400), Disposition::Permanent),
+            (rejected_sms(404), Disposition::Permanent),
+            (
+                SmsProviderError::RequestError("operation timed out".to_string()).into(),
+                Disposition::Transient,
+            ),
+            (
+                SmsProviderError::ResponseParseError("missing field `sid`".to_string()).into(),
+                Disposition::Permanent,
+            ),
+            (rejected_push(429), Disposition::Transient),
+            (rejected_push(500), Disposition::Transient),
+            (rejected_push(400), Disposition::Permanent),
+            (
+                PushProviderError::RequestError("operation timed out".to_string()).into(),
+                Disposition::Transient,
+            ),
+            (
+                PushProviderError::CredentialsError("invalid key".to_string()).into(),
+                Disposition::Permanent,
+            ),
+        ];
+
+        for (err, disposition) in cases {
+            assert_eq!(err.disposition(), disposition, "{}", err);
+        }
+    }
 }
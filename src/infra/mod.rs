@@ -1,2 +1,5 @@
 pub mod amqp;
+pub mod connection;
 pub mod consumer;
+pub mod organizations;
+pub mod sqlite;
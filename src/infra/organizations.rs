@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+use crate::domain::notification::NotificationChannel;
+use crate::infra::{amqp::AmqpPublisher, consumer::Consumers};
+use crate::organizations::store::{OrganizationStore, OrganizationStoreError};
+use crate::tracing::info;
+
+pub const NOTIFICATION_TYPES: [&str; 3] = ["email", "sms", "push"];
+
+// Organization IDs become part of queue names, topic routing keys and
+// directory names, so dots and wildcards are not allowed. They are bounded
+// like template IDs, leaving room for the suffixes of retry queue names
+// within the 255 bytes queue names are limited to.
+static ORGANIZATION_ID: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9][A-Za-z0-9_-]{0,127}$").unwrap());
+
+#[derive(Error, Debug)]
+pub enum OrganizationError {
+    #[error("Invalid organization ID: {0}")]
+    InvalidId(String),
+
+    #[error("Failed to provision organization {0}: {1}")]
+    ProvisioningError(String, String),
+
+    #[error(transparent)]
+    StoreError(#[from] OrganizationStoreError),
+}
+
+pub struct OrganizationRegistry {
+    organizations: RwLock<HashSet<String>>,
+    publisher: AmqpPublisher,
+    consumers: Consumers,
+    store: Arc<dyn OrganizationStore>,
+}
+
+impl OrganizationRegistry {
+    pub fn new(
+        publisher: AmqpPublisher,
+        consumers: Consumers,
+        store: Arc<dyn OrganizationStore>,
+    ) -> Self {
+        Self {
+            organizations: RwLock::new(HashSet::new()),
+            publisher,
+            consumers,
+            store,
+        }
+    }
+
+    /// Provisions the configured organizations along with the ones
+    /// registered through the API before the last restart. Returns how many
+    /// organizations are registered.
+    pub async fn load(&self, configured: &[String]) -> Result<usize, OrganizationError> {
+        let stored = self.store.list().await?;
+
+        let mut organizations = self.organizations.write().await;
+
+        for org_id in configured.iter().chain(&stored) {
+            if organizations.contains(org_id) {
+                continue;
+            }
+
+            if !ORGANIZATION_ID.is_match(org_id) {
+                return Err(OrganizationError::InvalidId(org_id.to_string()));
+            }
+
+            self.provision(org_id).await?;
+
+            organizations.insert(org_id.to_string());
+        }
+
+        Ok(organizations.len())
+    }
+
+    pub async fn contains(&self, org_id: &str) -> bool {
+        self.organizations.read().await.contains(org_id)
+    }
+
+    /// Whether notifications of the channel can be delivered
+    pub fn supports(&self, channel: NotificationChannel) -> bool {
+        self.consumers.supports(channel)
+    }
+
+    pub async fn list(&self) -> Vec<String> {
+        let mut organizations: Vec<String> =
+            self.organizations.read().await.iter().cloned().collect();
+
+        organizations.sort();
+
+        organizations
+    }
+
+    /// Declares the organization queues, starts its consumers and stores it
+    /// so it is provisioned again after a restart. Returns `false` when the
+    /// organization was already registered.
+    ///
+    /// A registration that fails stops the consumers it started, but keeps
+    /// the queues it declared, since they may already hold messages.
+    /// Declaring the queues and storing the organization can both be
+    /// repeated, so the registration can simply be retried.
+    pub async fn register(&self, org_id: &str) -> Result<bool, OrganizationError> {
+        if !ORGANIZATION_ID.is_match(org_id) {
+            return Err(OrganizationError::InvalidId(org_id.to_string()));
+        }
+
+        // The write lock is held while provisioning so concurrent
+        // registrations of the same organization don't start consumers twice
+        let mut organizations = self.organizations.write().await;
+
+        if organizations.contains(org_id) {
+            return Ok(false);
+        }
+
+        if let Err(err) = self.provision(org_id).await {
+            self.publisher.forget_queues(org_id);
+
+            return Err(err);
+        }
+
+        if let Err(err) = self.store.save(org_id).await {
+            // An organization that isn't stored would silently stop being
+            // consumed after a restart
+            self.consumers.stop(org_id).await;
+            self.publisher.forget_queues(org_id);
+
+            return Err(err.into());
+        }
+
+        organizations.insert(org_id.to_string());
+
+        info!("Organization {} registered", org_id);
+
+        Ok(true)
+    }
+
+    async fn provision(&self, org_id: &str) -> Result<(), OrganizationError> {
+        self.publisher
+            .setup_queues(org_id, &NOTIFICATION_TYPES)
+            .await
+            .map_err(|err| {
+                OrganizationError::ProvisioningError(org_id.to_string(), err.to_string())
+            })?;
+
+        self.consumers.start(org_id).await.map_err(|err| {
+            OrganizationError::ProvisioningError(org_id.to_string(), err.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn organization_ids_start_alphanumeric_and_are_bounded() {
+        for valid in ["org1", "Org_1", "org-1", &"a".repeat(128)] {
+            assert!(ORGANIZATION_ID.is_match(valid), "{}", valid);
+        }
+
+        for invalid in [
+            "",
+            "-org",
+            "_org",
+            "org.1",
+            "org*",
+            "org#",
+            &"a".repeat(129),
+        ] {
+            assert!(!ORGANIZATION_ID.is_match(invalid), "{}", invalid);
+        }
+    }
+}
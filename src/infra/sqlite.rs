@@ -0,0 +1,27 @@
+use std::str::FromStr;
+
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+
+/// Opens a pool to the database, creating its file if missing. Stores using
+/// the same database share its pool, and create their tables on it.
+pub async fn connect(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+
+    SqlitePoolOptions::new().connect_with(options).await
+}
+
+/// A private in-memory database. It lives as long as its single connection,
+/// so the pool never closes or replaces it.
+#[cfg(test)]
+pub async fn memory() -> SqlitePool {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
@@ -1,63 +1,253 @@
-use api::routes::create_router;
-use config::get_config;
-use infra::amqp::AmqpPublisher;
+use amqprs::connection::OpenConnectionArguments;
+use api::routes::{create_router, AppState};
+use config::{get_config, get_import_config, EmailTemplatesBackend, ImportConfig};
+use idempotency::{sqlite::SqliteIdempotencyStore, store::IdempotencyStore};
+use infra::{
+    amqp::{AmqpPublisher, RetryPolicy},
+    connection::{ConnectionManager, ReconnectPolicy},
+    consumer::Consumers,
+    organizations::OrganizationRegistry,
+    sqlite,
+};
+use organizations::{sqlite::SqliteOrganizationStore, store::OrganizationStore};
+use providers::email::{EmailSender, TestRecipients};
+use resend_rs::Resend;
+use status::{sqlite::SqliteNotificationStatusStore, store::NotificationStatusStore};
+use std::{env, sync::Arc, time::Duration};
+use templates::email::{
+    cache::{EmailTemplateCache, TemplateOptions},
+    import::import_templates,
+    repository::{FileEmailTemplateRepository, WritableEmailTemplateRepository},
+    sqlite::SqliteEmailTemplateRepository,
+    versions::{sqlite::SqliteTemplateVersionStore, store::TemplateVersionStore},
+};
 use tokio::signal;
 use tower_http::trace::TraceLayer;
-use tracing::{error, info, Tracing};
+use tracing::{error, info, warn, Tracing};
 
 pub mod api;
 pub mod config;
 pub mod domain;
+pub mod idempotency;
 pub mod infra;
+pub mod organizations;
+pub mod providers;
+pub mod status;
 pub mod templates;
 pub mod tracing;
 pub mod workers;
 
+/// How long an idempotency key claimed by a request in progress outlives the
+/// publish confirmation timeout
+const IDEMPOTENCY_PENDING_MARGIN: Duration = Duration::from_secs(30);
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     Tracing::init();
 
+    // The import only reads the template settings, so it runs without the
+    // broker and server settings
+    if env::args().nth(1).as_deref() == Some("import-email-templates") {
+        return import_email_templates(&get_import_config()).await;
+    }
+
     let config = get_config();
 
-    info!("Initing RabbitMQ publisher");
+    info!("Connecting to RabbitMQ");
 
-    let publisher = AmqpPublisher::new(
-        &config.rabbitmq_host,
-        config.rabbitmq_port,
-        &config.rabbitmq_user,
-        &config.rabbitmq_password,
-        "notifications",
+    let connection = ConnectionManager::connect(
+        OpenConnectionArguments::new(
+            &config.rabbitmq_host,
+            config.rabbitmq_port,
+            &config.rabbitmq_user,
+            &config.rabbitmq_password,
+        ),
+        ReconnectPolicy::new(
+            Duration::from_millis(config.reconnect_base_delay_ms),
+            Duration::from_millis(config.reconnect_max_delay_ms),
+        ),
     )
     .await
     .map_err(|err| {
-        error!("Failed to init RabbitMQ publisher: {}", err);
+        error!("Failed to connect to RabbitMQ: {}", err);
         err
     })?;
 
-    publisher
-        .setup_queues("organization-1", &["email", "sms", "push"])
+    let retry_policy = RetryPolicy::new(config.max_delivery_attempts, config.retry_base_delay_ms);
+
+    let publisher = AmqpPublisher::new(
+        "notifications",
+        retry_policy,
+        Duration::from_millis(config.publish_confirm_timeout_ms),
+        config.publisher_channels,
+    );
+
+    connection
+        .register(Arc::new(publisher.clone()))
         .await
         .map_err(|err| {
-            error!("Failed to setup queues: {}", err);
+            error!("Failed to init RabbitMQ publisher: {}", err);
             err
         })?;
 
     info!("RabbitMQ publisher inited");
 
-    tokio::spawn(async move {
-        info!("Starting consumers");
+    let database = sqlite::connect(&config.database_url).await.map_err(|err| {
+        error!("Failed to connect to the database: {}", err);
+        err
+    })?;
+
+    let statuses: Arc<dyn NotificationStatusStore> = Arc::new(
+        SqliteNotificationStatusStore::new(database.clone())
+            .await
+            .map_err(|err| {
+                error!("Failed to init notification status store: {}", err);
+                err
+            })?,
+    );
+
+    let idempotency: Arc<dyn IdempotencyStore> = Arc::new(
+        SqliteIdempotencyStore::new(
+            database.clone(),
+            Duration::from_secs(config.idempotency_window_secs),
+            // Claims outlive the publish confirmation of their request, and
+            // expire soon after when the request never completes
+            Duration::from_millis(config.publish_confirm_timeout_ms) + IDEMPOTENCY_PENDING_MARGIN,
+        )
+        .await
+        .map_err(|err| {
+            error!("Failed to init idempotency store: {}", err);
+            err
+        })?,
+    );
 
-        let _ = infra::consumer::start_consumers(config)
+    let template_versions: Arc<dyn TemplateVersionStore> = Arc::new(
+        SqliteTemplateVersionStore::new(database.clone())
             .await
             .map_err(|err| {
-                error!("Failed to start consumers: {}", err);
-                std::process::exit(1);
-            });
+                error!("Failed to init email template version store: {}", err);
+                err
+            })?,
+    );
+
+    let email_template_repository: Arc<dyn WritableEmailTemplateRepository> = match config
+        .email_templates_backend
+    {
+        EmailTemplatesBackend::File => Arc::new(FileEmailTemplateRepository::new(
+            config.email_templates_path.clone(),
+        )),
+        EmailTemplatesBackend::Sqlite => {
+            let templates_database = if config.email_templates_database_url == config.database_url {
+                database.clone()
+            } else {
+                sqlite::connect(&config.email_templates_database_url)
+                    .await
+                    .map_err(|err| {
+                        error!("Failed to connect to the email templates database: {}", err);
+                        err
+                    })?
+            };
 
-        info!("Consumers started");
-    });
+            Arc::new(
+                SqliteEmailTemplateRepository::new(templates_database)
+                    .await
+                    .map_err(|err| {
+                        error!("Failed to init email template repository: {}", err);
+                        err
+                    })?,
+            )
+        }
+    };
 
-    let app = create_router(publisher).layer(TraceLayer::new_for_http());
+    let watch_email_templates = config.watch_email_templates
+        && config.email_templates_backend == EmailTemplatesBackend::File;
+
+    let email_templates = Arc::new(
+        EmailTemplateCache::load(
+            email_template_repository.clone(),
+            template_versions,
+            TemplateOptions {
+                warn_only: config.email_templates_warn_only,
+                strict: config.strict_email_templates,
+                record_changes: watch_email_templates,
+            },
+        )
+        .await
+        .map_err(|err| {
+            error!("Failed to load email templates: {}", err);
+            err
+        })?,
+    );
+
+    if watch_email_templates {
+        email_templates
+            .watch(&config.email_templates_path)
+            .map_err(|err| {
+                error!("Failed to watch email templates: {}", err);
+                err
+            })?;
+    }
+
+    // Other instances sharing the database save and publish templates too
+    if config.email_templates_poll_interval_secs > 0 {
+        email_templates.poll(Duration::from_secs(
+            config.email_templates_poll_interval_secs,
+        ));
+    }
+
+    let email_sender = Arc::new(EmailSender::new(Resend::default()));
+
+    let consumers = Consumers::new(
+        config,
+        connection.clone(),
+        retry_policy,
+        publisher.clone(),
+        statuses.clone(),
+        email_templates.clone(),
+        email_sender.clone(),
+    )
+    .map_err(|err| {
+        error!("Failed to init consumers: {}", err);
+        err
+    })?;
+
+    let organization_store: Arc<dyn OrganizationStore> = Arc::new(
+        SqliteOrganizationStore::new(database)
+            .await
+            .map_err(|err| {
+                error!("Failed to init organization store: {}", err);
+                err
+            })?,
+    );
+
+    let organizations = Arc::new(OrganizationRegistry::new(
+        publisher.clone(),
+        consumers,
+        organization_store,
+    ));
+
+    let registered = organizations
+        .load(&config.organizations)
+        .await
+        .map_err(|err| {
+            error!("Failed to register organizations: {}", err);
+            err
+        })?;
+
+    info!("{} organizations registered", registered);
+
+    let app = create_router(AppState {
+        publisher,
+        organizations,
+        statuses,
+        idempotency,
+        email_templates,
+        email_template_repository,
+        email_sender,
+        test_recipients: Arc::new(TestRecipients::new(&config.test_email_recipients)),
+        connection,
+    })
+    .layer(TraceLayer::new_for_http());
 
     let listener_address = format!("0.0.0.0:{}", config.port);
 
@@ -75,6 +265,52 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Copies the JSON templates under `EMAIL_TEMPLATES_PATH` into the database
+/// at `EMAIL_TEMPLATES_DATABASE_URL`, before switching the backend to `sqlite`
+async fn import_email_templates(
+    config: &ImportConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    info!(
+        "Importing email templates from {} into {}",
+        config.email_templates_path, config.email_templates_database_url
+    );
+
+    let source = FileEmailTemplateRepository::new(config.email_templates_path.clone());
+
+    let database = sqlite::connect(&config.email_templates_database_url)
+        .await
+        .map_err(|err| {
+            error!("Failed to connect to the email templates database: {}", err);
+            err
+        })?;
+
+    let target = SqliteEmailTemplateRepository::new(database)
+        .await
+        .map_err(|err| {
+            error!("Failed to init email template repository: {}", err);
+            err
+        })?;
+
+    let summary = import_templates(&source, &target).await.map_err(|err| {
+        error!("Failed to import email templates: {}", err);
+        err
+    })?;
+
+    info!(
+        "Imported {} email templates, {} translations, {} partials and {} layouts",
+        summary.templates, summary.translations, summary.partials, summary.layouts
+    );
+
+    if summary.skipped > 0 {
+        warn!(
+            "Skipped {} email templates that failed to parse",
+            summary.skipped
+        );
+    }
+
+    Ok(())
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
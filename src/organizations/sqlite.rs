@@ -0,0 +1,55 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::SqlitePool;
+
+use super::store::{OrganizationStore, OrganizationStoreError};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS organizations (
+    id TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL
+);
+"#;
+
+pub struct SqliteOrganizationStore {
+    pool: SqlitePool,
+}
+
+impl SqliteOrganizationStore {
+    pub async fn new(pool: SqlitePool) -> Result<Self, OrganizationStoreError> {
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .map_err(store_error)?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl OrganizationStore for SqliteOrganizationStore {
+    async fn save(&self, org_id: &str) -> Result<(), OrganizationStoreError> {
+        sqlx::query(
+            "INSERT INTO organizations (id, created_at) VALUES (?, ?)
+             ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(org_id)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<String>, OrganizationStoreError> {
+        sqlx::query_scalar("SELECT id FROM organizations ORDER BY id")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(store_error)
+    }
+}
+
+fn store_error(err: sqlx::Error) -> OrganizationStoreError {
+    OrganizationStoreError::StoreError(err.to_string())
+}
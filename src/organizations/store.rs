@@ -0,0 +1,19 @@
+use async_trait::async_trait;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum OrganizationStoreError {
+    #[error("Organization store error: {0}")]
+    StoreError(String),
+}
+
+/// Organizations registered through the API, provisioned again on startup
+/// along with the configured ones
+#[async_trait]
+pub trait OrganizationStore: Send + Sync {
+    /// Stores the organization, doing nothing if it is already stored
+    async fn save(&self, org_id: &str) -> Result<(), OrganizationStoreError>;
+
+    /// Every stored organization, sorted
+    async fn list(&self) -> Result<Vec<String>, OrganizationStoreError>;
+}
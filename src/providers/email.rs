@@ -0,0 +1,55 @@
+use std::collections::HashSet;
+
+use resend_rs::{types::CreateEmailBaseOptions, Resend};
+
+use crate::templates::email::engine::RenderedEmail;
+
+const FROM: &str = "Crab Notifications <onboarding@resend.dev>"; // Local development e-mail source
+
+/// Delivers rendered emails through Resend, for the email worker and for
+/// test sends of templates
+pub struct EmailSender {
+    resend: Resend,
+}
+
+impl EmailSender {
+    pub fn new(resend: Resend) -> Self {
+        Self { resend }
+    }
+
+    /// Sends the email and returns the provider's message ID
+    pub async fn send(
+        &self,
+        recipient: &str,
+        rendered: &RenderedEmail,
+    ) -> Result<String, resend_rs::Error> {
+        let email = CreateEmailBaseOptions::new(FROM, [recipient], &rendered.subject)
+            .with_html(&rendered.html)
+            .with_text(&rendered.text);
+
+        let email = self.resend.emails.send(email).await?;
+
+        Ok(email.id.to_string())
+    }
+}
+
+/// Addresses templates can be test-sent to, compared case-insensitively.
+/// Test sends are disabled when the list is empty.
+pub struct TestRecipients {
+    recipients: HashSet<String>,
+}
+
+impl TestRecipients {
+    pub fn new(recipients: &[String]) -> Self {
+        Self {
+            recipients: recipients
+                .iter()
+                .map(|recipient| recipient.to_lowercase())
+                .collect(),
+        }
+    }
+
+    pub fn allows(&self, recipient: &str) -> bool {
+        self.recipients.contains(&recipient.to_lowercase())
+    }
+}
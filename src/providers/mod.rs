@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+use reqwest::Client;
+
+pub mod email;
+pub mod push;
+pub mod sms;
+
+/// A provider that doesn't answer within this time is treated as unreachable,
+/// so a hung request can't hold a consumer's delivery forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HTTP client shared by the providers calling out over reqwest. Like
+/// `Client::new`, this panics only if the TLS backend can't be initialized.
+pub(crate) fn http_client() -> Client {
+    Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .expect("failed to initialize the HTTP client")
+}
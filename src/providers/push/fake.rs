@@ -0,0 +1,42 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use super::{PushMessage, PushProvider, PushProviderError};
+
+/// Keeps sent messages in memory instead of delivering them
+#[derive(Default)]
+pub struct FakePushProvider {
+    sent: Mutex<Vec<PushMessage>>,
+    failures: Mutex<VecDeque<PushProviderError>>,
+}
+
+impl FakePushProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent(&self) -> Vec<PushMessage> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Fails the next send with the error instead of keeping the message.
+    /// Queued failures are returned in order.
+    pub fn fail_next(&self, err: PushProviderError) {
+        self.failures.lock().unwrap().push_back(err);
+    }
+}
+
+#[async_trait]
+impl PushProvider for FakePushProvider {
+    async fn send(&self, message: &PushMessage) -> Result<String, PushProviderError> {
+        if let Some(err) = self.failures.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+
+        self.sent.lock().unwrap().push(message.clone());
+
+        Ok(Uuid::new_v4().to_string())
+    }
+}
@@ -0,0 +1,241 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use super::{PushMessage, PushProvider, PushProviderError};
+use crate::providers::http_client;
+
+const FCM_API_URL: &str = "https://fcm.googleapis.com/v1/projects";
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// Access tokens are refreshed this many seconds before they actually expire
+const TOKEN_EXPIRY_MARGIN_SECS: i64 = 60;
+
+#[derive(Debug, Deserialize)]
+struct ServiceAccount {
+    project_id: String,
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ServiceAccountClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct FcmMessageResponse {
+    name: String,
+}
+
+struct AccessToken {
+    value: String,
+    expires_at: i64,
+}
+
+pub struct FcmPushProvider {
+    client: Client,
+    service_account: ServiceAccount,
+    encoding_key: EncodingKey,
+    access_token: Mutex<Option<AccessToken>>,
+}
+
+impl FcmPushProvider {
+    pub fn from_service_account_file(path: &str) -> Result<Self, PushProviderError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| PushProviderError::CredentialsError(err.to_string()))?;
+
+        let service_account: ServiceAccount = serde_json::from_str(&content)
+            .map_err(|err| PushProviderError::CredentialsError(err.to_string()))?;
+
+        let encoding_key = EncodingKey::from_rsa_pem(service_account.private_key.as_bytes())
+            .map_err(|err| PushProviderError::CredentialsError(err.to_string()))?;
+
+        Ok(Self {
+            client: http_client(),
+            service_account,
+            encoding_key,
+            access_token: Mutex::new(None),
+        })
+    }
+
+    async fn access_token(&self) -> Result<String, PushProviderError> {
+        let mut access_token = self.access_token.lock().await;
+        let now = Utc::now().timestamp();
+
+        if let Some(token) = access_token.as_ref() {
+            if token.expires_at - TOKEN_EXPIRY_MARGIN_SECS > now {
+                return Ok(token.value.clone());
+            }
+        }
+
+        let claims = ServiceAccountClaims {
+            iss: &self.service_account.client_email,
+            scope: FCM_SCOPE,
+            aud: &self.service_account.token_uri,
+            iat: now,
+            exp: now + 3600,
+        };
+
+        let assertion = encode(&Header::new(Algorithm::RS256), &claims, &self.encoding_key)
+            .map_err(|err| PushProviderError::CredentialsError(err.to_string()))?;
+
+        let params = [
+            ("grant_type", JWT_BEARER_GRANT_TYPE),
+            ("assertion", assertion.as_str()),
+        ];
+
+        let response = self
+            .client
+            .post(&self.service_account.token_uri)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|err| PushProviderError::RequestError(err.to_string()))?;
+
+        let status = response.status();
+
+        // An outage of the token endpoint is retried like one of FCM itself
+        if status.as_u16() == 429 || status.is_server_error() {
+            let message = response.text().await.unwrap_or_default();
+
+            return Err(PushProviderError::Rejected {
+                status: status.as_u16(),
+                message: format!("token exchange failed: {}", message),
+            });
+        }
+
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+
+            return Err(PushProviderError::CredentialsError(format!(
+                "token exchange failed with status {}: {}",
+                status, message
+            )));
+        }
+
+        let token = response
+            .json::<TokenResponse>()
+            .await
+            .map_err(parse_error)?;
+
+        let value = token.access_token.clone();
+
+        *access_token = Some(AccessToken {
+            value: token.access_token,
+            expires_at: now + token.expires_in,
+        });
+
+        Ok(value)
+    }
+
+    /// Drops the cached access token once FCM rejects it, unless another
+    /// send already replaced it
+    async fn invalidate_access_token(&self, rejected: &str) {
+        let mut access_token = self.access_token.lock().await;
+
+        if access_token
+            .as_ref()
+            .is_some_and(|token| token.value == rejected)
+        {
+            *access_token = None;
+        }
+    }
+
+    async fn post_message(
+        &self,
+        access_token: &str,
+        payload: &serde_json::Value,
+    ) -> Result<reqwest::Response, PushProviderError> {
+        let url = format!(
+            "{}/{}/messages:send",
+            FCM_API_URL, self.service_account.project_id
+        );
+
+        self.client
+            .post(&url)
+            .bearer_auth(access_token)
+            .json(payload)
+            .send()
+            .await
+            .map_err(|err| PushProviderError::RequestError(err.to_string()))
+    }
+}
+
+#[async_trait]
+impl PushProvider for FcmPushProvider {
+    async fn send(&self, message: &PushMessage) -> Result<String, PushProviderError> {
+        let mut payload = json!({
+            "message": {
+                "token": message.device_token,
+                "notification": {
+                    "title": message.title,
+                },
+            }
+        });
+
+        if let Some(description) = &message.description {
+            payload["message"]["notification"]["body"] = json!(description);
+        }
+
+        if let Some(module) = &message.module {
+            payload["message"]["data"] = json!({ "module": module });
+        }
+
+        let access_token = self.access_token().await?;
+        let mut response = self.post_message(&access_token, &payload).await?;
+
+        // A cached token revoked before its expiry is exchanged again, and
+        // the message sent once more with the new one
+        if response.status() == StatusCode::UNAUTHORIZED {
+            self.invalidate_access_token(&access_token).await;
+
+            let access_token = self.access_token().await?;
+            response = self.post_message(&access_token, &payload).await?;
+        }
+
+        let status = response.status();
+
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+
+            return Err(PushProviderError::Rejected {
+                status: status.as_u16(),
+                message,
+            });
+        }
+
+        let body = response
+            .json::<FcmMessageResponse>()
+            .await
+            .map_err(parse_error)?;
+
+        Ok(body.name)
+    }
+}
+
+/// A body that timed out while being read may still arrive on a retry, one
+/// that arrived but doesn't parse won't
+fn parse_error(err: reqwest::Error) -> PushProviderError {
+    if err.is_timeout() {
+        PushProviderError::RequestError(err.to_string())
+    } else {
+        PushProviderError::ResponseParseError(err.to_string())
+    }
+}
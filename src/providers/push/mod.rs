@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+use thiserror::Error;
+
+pub mod fake;
+pub mod fcm;
+
+#[derive(Error, Debug)]
+pub enum PushProviderError {
+    #[error("Invalid push provider credentials: {0}")]
+    CredentialsError(String),
+
+    #[error("Failed to reach push provider: {0}")]
+    RequestError(String),
+
+    #[error("Push provider rejected the message with status {status}: {message}")]
+    Rejected { status: u16, message: String },
+
+    #[error("Failed to parse push provider response: {0}")]
+    ResponseParseError(String),
+}
+
+impl PushProviderError {
+    /// Network failures, rate limits and provider-side errors may succeed
+    /// when retried, other rejections won't. Bad credentials never heal, and
+    /// an unparseable success response means the message was accepted already.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            PushProviderError::RequestError(_) => true,
+            PushProviderError::Rejected { status, .. } => *status == 429 || *status >= 500,
+            PushProviderError::CredentialsError(_) | PushProviderError::ResponseParseError(_) => {
+                false
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PushMessage {
+    pub device_token: String,
+    pub module: Option<String>,
+    pub title: String,
+    pub description: Option<String>,
+}
+
+#[async_trait]
+pub trait PushProvider: Send + Sync {
+    /// Sends the message and returns the provider's message ID
+    async fn send(&self, message: &PushMessage) -> Result<String, PushProviderError>;
+}
@@ -0,0 +1,42 @@
+use async_trait::async_trait;
+use thiserror::Error;
+
+pub mod fake;
+pub mod twilio;
+
+#[derive(Error, Debug)]
+pub enum SmsProviderError {
+    #[error("Failed to reach SMS provider: {0}")]
+    RequestError(String),
+
+    #[error("SMS provider rejected the message with status {status}: {message}")]
+    Rejected { status: u16, message: String },
+
+    #[error("Failed to parse SMS provider response: {0}")]
+    ResponseParseError(String),
+}
+
+impl SmsProviderError {
+    /// Network failures, rate limits and provider-side errors may succeed
+    /// when retried, other rejections won't. An unparseable success response
+    /// is not retried since the message was accepted already.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SmsProviderError::RequestError(_) => true,
+            SmsProviderError::Rejected { status, .. } => *status == 429 || *status >= 500,
+            SmsProviderError::ResponseParseError(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SmsMessage {
+    pub to: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait SmsProvider: Send + Sync {
+    /// Sends the message and returns the provider's message ID
+    async fn send(&self, message: &SmsMessage) -> Result<String, SmsProviderError>;
+}
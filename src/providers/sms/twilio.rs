@@ -0,0 +1,93 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+
+use super::{SmsMessage, SmsProvider, SmsProviderError};
+use crate::providers::http_client;
+
+const TWILIO_API_URL: &str = "https://api.twilio.com/2010-04-01";
+
+pub struct TwilioSmsProvider {
+    client: Client,
+    account_sid: String,
+    auth_token: String,
+    from: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TwilioMessageResponse {
+    sid: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TwilioErrorResponse {
+    message: String,
+}
+
+impl TwilioSmsProvider {
+    pub fn new(account_sid: String, auth_token: String, from: String) -> Self {
+        Self {
+            client: http_client(),
+            account_sid,
+            auth_token,
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl SmsProvider for TwilioSmsProvider {
+    async fn send(&self, message: &SmsMessage) -> Result<String, SmsProviderError> {
+        let url = format!(
+            "{}/Accounts/{}/Messages.json",
+            TWILIO_API_URL, self.account_sid
+        );
+
+        let params = [
+            ("To", message.to.as_str()),
+            ("From", self.from.as_str()),
+            ("Body", message.body.as_str()),
+        ];
+
+        let response = self
+            .client
+            .post(&url)
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .form(&params)
+            .send()
+            .await
+            .map_err(|err| SmsProviderError::RequestError(err.to_string()))?;
+
+        let status = response.status();
+
+        if !status.is_success() {
+            let message = response
+                .json::<TwilioErrorResponse>()
+                .await
+                .map(|body| body.message)
+                .unwrap_or_else(|err| err.to_string());
+
+            return Err(SmsProviderError::Rejected {
+                status: status.as_u16(),
+                message,
+            });
+        }
+
+        let body = response
+            .json::<TwilioMessageResponse>()
+            .await
+            .map_err(parse_error)?;
+
+        Ok(body.sid)
+    }
+}
+
+/// A body that timed out while being read may still arrive on a retry, one
+/// that arrived but doesn't parse won't
+fn parse_error(err: reqwest::Error) -> SmsProviderError {
+    if err.is_timeout() {
+        SmsProviderError::RequestError(err.to_string())
+    } else {
+        SmsProviderError::ResponseParseError(err.to_string())
+    }
+}
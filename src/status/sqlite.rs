@@ -0,0 +1,399 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{FromRow, SqlitePool};
+
+use super::store::{
+    NotificationRecord, NotificationStatusStore, StatusError, StatusEvent, StatusUpdate,
+};
+use crate::domain::notification::{NotificationChannel, NotificationStatus};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS notifications (
+    id TEXT PRIMARY KEY,
+    organization_id TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    status TEXT NOT NULL,
+    provider_message_id TEXT,
+    error TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS notification_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    notification_id TEXT NOT NULL REFERENCES notifications (id),
+    status TEXT NOT NULL,
+    detail TEXT,
+    occurred_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS notification_events_notification_id
+    ON notification_events (notification_id);
+"#;
+
+#[derive(FromRow)]
+struct NotificationRow {
+    id: String,
+    organization_id: String,
+    channel: String,
+    status: String,
+    provider_message_id: Option<String>,
+    error: Option<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+#[derive(FromRow)]
+struct EventRow {
+    status: String,
+    detail: Option<String>,
+    occurred_at: String,
+}
+
+pub struct SqliteNotificationStatusStore {
+    pool: SqlitePool,
+}
+
+impl SqliteNotificationStatusStore {
+    pub async fn new(pool: SqlitePool) -> Result<Self, StatusError> {
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .map_err(store_error)?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl NotificationStatusStore for SqliteNotificationStatusStore {
+    async fn create(
+        &self,
+        id: &str,
+        organization_id: &str,
+        channel: NotificationChannel,
+    ) -> Result<(), StatusError> {
+        let now = Utc::now().to_rfc3339();
+        let status = NotificationStatus::Accepted.as_str();
+
+        let mut transaction = self.pool.begin().await.map_err(store_error)?;
+
+        sqlx::query(
+            "INSERT INTO notifications (id, organization_id, channel, status, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(organization_id)
+        .bind(channel.as_str())
+        .bind(status)
+        .bind(&now)
+        .bind(&now)
+        .execute(&mut *transaction)
+        .await
+        .map_err(store_error)?;
+
+        sqlx::query(
+            "INSERT INTO notification_events (notification_id, status, occurred_at)
+             VALUES (?, ?, ?)",
+        )
+        .bind(id)
+        .bind(status)
+        .bind(&now)
+        .execute(&mut *transaction)
+        .await
+        .map_err(store_error)?;
+
+        transaction.commit().await.map_err(store_error)?;
+
+        Ok(())
+    }
+
+    async fn transition(
+        &self,
+        id: &str,
+        status: NotificationStatus,
+        update: StatusUpdate,
+    ) -> Result<(), StatusError> {
+        let now = Utc::now().to_rfc3339();
+        let detail = update
+            .provider_message_id
+            .clone()
+            .or_else(|| update.error.clone());
+
+        let mut transaction = self.pool.begin().await.map_err(store_error)?;
+
+        // A consumer may pick the message up before the API records it as
+        // published, so `published` never overrides a later status. A sent
+        // notification stays sent whatever a redelivery of it records.
+        let result = sqlx::query(
+            "UPDATE notifications
+             SET status = CASE
+                     WHEN status = 'sent' THEN status
+                     WHEN ?1 = 'published' AND status != 'accepted' THEN status
+                     ELSE ?1
+                 END,
+                 provider_message_id = COALESCE(?2, provider_message_id),
+                 error = CASE WHEN status = 'sent' OR ?1 = 'sent' THEN NULL ELSE COALESCE(?3, error) END,
+                 updated_at = ?4
+             WHERE id = ?5",
+        )
+        .bind(status.as_str())
+        .bind(&update.provider_message_id)
+        .bind(&update.error)
+        .bind(&now)
+        .bind(id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(store_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(StatusError::NotFound(id.to_string()));
+        }
+
+        sqlx::query(
+            "INSERT INTO notification_events (notification_id, status, detail, occurred_at)
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(status.as_str())
+        .bind(&detail)
+        .bind(&now)
+        .execute(&mut *transaction)
+        .await
+        .map_err(store_error)?;
+
+        transaction.commit().await.map_err(store_error)?;
+
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<NotificationRecord>, StatusError> {
+        let row: Option<NotificationRow> = sqlx::query_as(
+            "SELECT id, organization_id, channel, status, provider_message_id, error, created_at, updated_at
+             FROM notifications
+             WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let events: Vec<EventRow> = sqlx::query_as(
+            "SELECT status, detail, occurred_at
+             FROM notification_events
+             WHERE notification_id = ?
+             ORDER BY id",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        let history = events
+            .into_iter()
+            .map(|event| {
+                Ok(StatusEvent {
+                    status: parse_status(&event.status)?,
+                    detail: event.detail,
+                    occurred_at: event.occurred_at,
+                })
+            })
+            .collect::<Result<Vec<_>, StatusError>>()?;
+
+        let channel = NotificationChannel::parse(&row.channel).ok_or_else(|| {
+            StatusError::StoreError(format!("Unknown notification channel: {}", row.channel))
+        })?;
+
+        Ok(Some(NotificationRecord {
+            id: row.id,
+            organization_id: row.organization_id,
+            channel,
+            status: parse_status(&row.status)?,
+            provider_message_id: row.provider_message_id,
+            error: row.error,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            history,
+        }))
+    }
+}
+
+fn parse_status(value: &str) -> Result<NotificationStatus, StatusError> {
+    NotificationStatus::parse(value)
+        .ok_or_else(|| StatusError::StoreError(format!("Unknown notification status: {}", value)))
+}
+
+fn store_error(err: sqlx::Error) -> StatusError {
+    StatusError::StoreError(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::sqlite;
+
+    async fn store_with(id: &str) -> SqliteNotificationStatusStore {
+        let store = SqliteNotificationStatusStore::new(sqlite::memory().await)
+            .await
+            .unwrap();
+
+        store
+            .create(id, "acme", NotificationChannel::Email)
+            .await
+            .unwrap();
+
+        store
+    }
+
+    async fn status_of(store: &SqliteNotificationStatusStore, id: &str) -> NotificationRecord {
+        store.find_by_id(id).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_notification_moves_through_its_statuses() {
+        let store = store_with("n1").await;
+
+        for status in [
+            NotificationStatus::Published,
+            NotificationStatus::Processing,
+            NotificationStatus::Sent,
+        ] {
+            let update = match status {
+                NotificationStatus::Sent => StatusUpdate::sent("provider-1".to_string()),
+                _ => StatusUpdate::default(),
+            };
+
+            store.transition("n1", status, update).await.unwrap();
+        }
+
+        let record = status_of(&store, "n1").await;
+
+        assert_eq!(record.status, NotificationStatus::Sent);
+        assert_eq!(record.provider_message_id.as_deref(), Some("provider-1"));
+        assert_eq!(
+            record
+                .history
+                .iter()
+                .map(|event| event.status)
+                .collect::<Vec<_>>(),
+            [
+                NotificationStatus::Accepted,
+                NotificationStatus::Published,
+                NotificationStatus::Processing,
+                NotificationStatus::Sent,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn published_does_not_override_a_later_status() {
+        let store = store_with("n1").await;
+
+        store
+            .transition(
+                "n1",
+                NotificationStatus::Processing,
+                StatusUpdate::default(),
+            )
+            .await
+            .unwrap();
+        store
+            .transition("n1", NotificationStatus::Published, StatusUpdate::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            status_of(&store, "n1").await.status,
+            NotificationStatus::Processing
+        );
+    }
+
+    #[tokio::test]
+    async fn a_redelivered_sent_notification_is_not_downgraded() {
+        let store = store_with("n1").await;
+
+        store
+            .transition(
+                "n1",
+                NotificationStatus::Sent,
+                StatusUpdate::sent("provider-1".to_string()),
+            )
+            .await
+            .unwrap();
+
+        store
+            .transition(
+                "n1",
+                NotificationStatus::Processing,
+                StatusUpdate::default(),
+            )
+            .await
+            .unwrap();
+        store
+            .transition(
+                "n1",
+                NotificationStatus::Retrying,
+                StatusUpdate::failed("timed out".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let record = status_of(&store, "n1").await;
+
+        assert_eq!(record.status, NotificationStatus::Sent);
+        assert_eq!(record.provider_message_id.as_deref(), Some("provider-1"));
+        assert_eq!(record.error, None);
+    }
+
+    #[tokio::test]
+    async fn a_retried_notification_keeps_its_error_until_sent() {
+        let store = store_with("n1").await;
+
+        store
+            .transition(
+                "n1",
+                NotificationStatus::Retrying,
+                StatusUpdate::failed("rate limited".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let record = status_of(&store, "n1").await;
+
+        assert_eq!(record.status, NotificationStatus::Retrying);
+        assert_eq!(record.error.as_deref(), Some("rate limited"));
+
+        store
+            .transition(
+                "n1",
+                NotificationStatus::Sent,
+                StatusUpdate::sent("provider-1".to_string()),
+            )
+            .await
+            .unwrap();
+
+        let record = status_of(&store, "n1").await;
+
+        assert_eq!(record.status, NotificationStatus::Sent);
+        assert_eq!(record.error, None);
+    }
+
+    #[tokio::test]
+    async fn transitioning_an_unknown_notification_is_not_found() {
+        let store = store_with("n1").await;
+
+        let result = store
+            .transition(
+                "n2",
+                NotificationStatus::Processing,
+                StatusUpdate::default(),
+            )
+            .await;
+
+        assert!(matches!(result, Err(StatusError::NotFound(id)) if id == "n2"));
+    }
+}
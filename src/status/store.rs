@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use thiserror::Error;
+
+use crate::domain::notification::{NotificationChannel, NotificationStatus};
+
+#[derive(Error, Debug)]
+pub enum StatusError {
+    #[error("Notification not found: {0}")]
+    NotFound(String),
+
+    #[error("Status store error: {0}")]
+    StoreError(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusEvent {
+    pub status: NotificationStatus,
+    pub detail: Option<String>,
+    pub occurred_at: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NotificationRecord {
+    pub id: String,
+    pub organization_id: String,
+    pub channel: NotificationChannel,
+    pub status: NotificationStatus,
+    pub provider_message_id: Option<String>,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub history: Vec<StatusEvent>,
+}
+
+/// Extra data recorded along with a status change
+#[derive(Debug, Clone, Default)]
+pub struct StatusUpdate {
+    pub provider_message_id: Option<String>,
+    pub error: Option<String>,
+}
+
+impl StatusUpdate {
+    pub fn sent(provider_message_id: String) -> Self {
+        Self {
+            provider_message_id: Some(provider_message_id),
+            error: None,
+        }
+    }
+
+    pub fn failed(error: String) -> Self {
+        Self {
+            provider_message_id: None,
+            error: Some(error),
+        }
+    }
+}
+
+#[async_trait]
+pub trait NotificationStatusStore: Send + Sync {
+    /// Records a new notification in the `Accepted` status
+    async fn create(
+        &self,
+        id: &str,
+        organization_id: &str,
+        channel: NotificationChannel,
+    ) -> Result<(), StatusError>;
+
+    async fn transition(
+        &self,
+        id: &str,
+        status: NotificationStatus,
+        update: StatusUpdate,
+    ) -> Result<(), StatusError>;
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<NotificationRecord>, StatusError>;
+}
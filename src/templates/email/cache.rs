@@ -0,0 +1,723 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use super::{
+    engine::{EmailTemplateEngine, RenderedEmail},
+    repository::{EmailTemplateRepository, TemplatePartial},
+    template::{EmailTemplate, TemplateError, TemplateKey},
+    variables::{validate_metadata, VariableError},
+    versions::store::{TemplateSnapshot, TemplateVersion, TemplateVersionStore, VersionError},
+};
+use crate::tracing::{error, info, warn};
+
+/// How long to wait for a burst of file events to settle before reloading,
+/// since editors often write a file in several steps
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// How many engines of pinned versions other than the published ones are
+/// kept compiled
+const PINNED_ENGINES: usize = 64;
+
+/// `{{> name}}` and `{{#> name}}` references to a partial or layout
+static PARTIAL_REFERENCE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{\{~?#?>\s*([A-Za-z0-9][A-Za-z0-9_-]*)").unwrap());
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TemplateOptions {
+    /// Skip broken templates instead of failing the whole load
+    pub warn_only: bool,
+    /// Fail on missing variables in templates that don't set `strict`
+    pub strict: bool,
+    /// Record and publish stored templates that changed since their latest
+    /// version when reloading, so edited files take effect right away
+    pub record_changes: bool,
+}
+
+/// Keeps the published version of every email template precompiled in
+/// memory, each in an engine of its own with the partials and layouts of its
+/// version. Reloads build new engines and swap them in whole, so renders
+/// never see a partial reload.
+///
+/// Saving a template records it as a new version and publishes it. Reloads
+/// compile the published versions, recording the stored templates of a
+/// template that has none, or that changed when `record_changes` is set.
+/// Notifications pinned to another version render it from the version store.
+pub struct EmailTemplateCache {
+    repository: Arc<dyn EmailTemplateRepository>,
+    versions: Arc<dyn TemplateVersionStore>,
+    engines: RwLock<Arc<HashMap<TemplateKey, Arc<EmailTemplateEngine>>>>,
+    // Held from reading the stores until the engines are swapped in, so a
+    // reload can't drop a template recorded or removed while it compiled
+    updates: tokio::sync::Mutex<()>,
+    pinned: Mutex<PinnedEngines>,
+    options: TemplateOptions,
+}
+
+/// Engines of the versions notifications were pinned to, evicting the
+/// oldest ones once full. Versions never change once recorded, so their
+/// engines don't need to be reloaded.
+#[derive(Default)]
+struct PinnedEngines {
+    engines: HashMap<(TemplateKey, i64), Arc<EmailTemplateEngine>>,
+    order: VecDeque<(TemplateKey, i64)>,
+}
+
+impl PinnedEngines {
+    fn get(&self, key: &TemplateKey, version: i64) -> Option<Arc<EmailTemplateEngine>> {
+        self.engines.get(&(key.clone(), version)).cloned()
+    }
+
+    fn insert(&mut self, key: &TemplateKey, version: i64, engine: Arc<EmailTemplateEngine>) {
+        let entry = (key.clone(), version);
+
+        if self.engines.insert(entry.clone(), engine).is_some() {
+            return;
+        }
+
+        self.order.push_back(entry);
+
+        while self.order.len() > PINNED_ENGINES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.engines.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl EmailTemplateCache {
+    /// Compiles every template of the repository, logging each broken one.
+    /// Fails if any template is broken, unless `warn_only` is set.
+    pub async fn load(
+        repository: Arc<dyn EmailTemplateRepository>,
+        versions: Arc<dyn TemplateVersionStore>,
+        options: TemplateOptions,
+    ) -> Result<Self, TemplateError> {
+        let engines = compile(repository.as_ref(), versions.as_ref(), options).await?;
+
+        info!("Loaded {} email templates", engines.len());
+
+        Ok(Self {
+            repository,
+            versions,
+            engines: RwLock::new(Arc::new(engines)),
+            updates: tokio::sync::Mutex::new(()),
+            pinned: Mutex::new(PinnedEngines::default()),
+            options,
+        })
+    }
+
+    /// Renders a template in the translation for the locale, see
+    /// `EmailTemplateEngine::render_registered`. A pinned version other than
+    /// the published one is loaded from the version store.
+    pub async fn render(
+        &self,
+        key: &TemplateKey,
+        version: Option<i64>,
+        locale: Option<&str>,
+        metadata: &Value,
+    ) -> Result<RenderedEmail, TemplateError> {
+        let engine = match (version, self.engine(key)) {
+            (Some(version), Some(engine)) if engine.version(&key.id) == Some(version) => engine,
+            (Some(version), _) => self.pinned_engine(key, version).await?,
+            (None, Some(engine)) => engine,
+            (None, None) => return Err(TemplateError::NotFound(key.to_string())),
+        };
+
+        engine.render_registered(&key.id, locale, metadata)
+    }
+
+    /// The template an organization sends under an ID: its own template if
+    /// it has one, or else the shared one
+    pub fn resolve(&self, organization_id: &str, id: &str) -> Option<TemplateKey> {
+        let engines = self.engines.read().unwrap();
+
+        [
+            TemplateKey::owned(organization_id, id),
+            TemplateKey::shared(id),
+        ]
+        .into_iter()
+        .find(|key| engines.contains_key(key))
+    }
+
+    /// Compiles a template with the current partials and layouts, without
+    /// registering it. Fails when any partial or layout is broken too, since
+    /// the template would be published along with them.
+    pub async fn check(&self, template: &EmailTemplate) -> Result<(), TemplateError> {
+        let (mut engine, failures) = shared_engine(self.repository.as_ref(), self.options).await?;
+
+        if failures > 0 {
+            return Err(TemplateError::BrokenPartials(failures));
+        }
+
+        engine.register(template)
+    }
+
+    /// The version new notifications of the template are pinned to
+    pub fn published_version(&self, key: &TemplateKey) -> Option<i64> {
+        self.engine(key)?.version(&key.id)
+    }
+
+    pub async fn list_versions(
+        &self,
+        key: &TemplateKey,
+    ) -> Result<Vec<TemplateVersion>, VersionError> {
+        self.versions.list(key).await
+    }
+
+    pub async fn find_version(
+        &self,
+        key: &TemplateKey,
+        version: i64,
+    ) -> Result<TemplateVersion, VersionError> {
+        self.versions.find(key, version).await
+    }
+
+    /// Records the stored template and its translations, with the current
+    /// partials and layouts they use, as a new version and publishes it
+    pub async fn record(&self, key: &TemplateKey) -> Result<TemplateVersion, VersionError> {
+        let _updates = self.updates.lock().await;
+        let repository = self.repository.as_ref();
+
+        let snapshot = read_snapshot(
+            repository,
+            key,
+            repository.list_locales(key).await?,
+            &repository.list_partials().await?,
+            &repository.list_layouts().await?,
+        )
+        .await?;
+
+        let mut engine = snapshot_engine(&snapshot, self.options)?;
+        let recorded = self.versions.record(key, &snapshot).await?;
+
+        engine.set_version(&key.id, recorded.version);
+        self.swap(key, Some(engine));
+
+        info!(
+            "Published version {} of email template {}",
+            recorded.version, key
+        );
+
+        Ok(recorded)
+    }
+
+    /// Publishes a version, once it compiles
+    pub async fn publish(
+        &self,
+        key: &TemplateKey,
+        version: i64,
+    ) -> Result<TemplateVersion, VersionError> {
+        let target = self.versions.find(key, version).await?;
+
+        self.publish_version(target).await
+    }
+
+    /// Publishes the version before the published one, once it compiles
+    pub async fn rollback(&self, key: &TemplateKey) -> Result<TemplateVersion, VersionError> {
+        let target = self.versions.previous(key).await?;
+
+        self.publish_version(target).await
+    }
+
+    /// Stops rendering a deleted template. Its versions are kept.
+    pub async fn remove(&self, key: &TemplateKey) {
+        let _updates = self.updates.lock().await;
+
+        self.swap(key, None);
+    }
+
+    /// Checks notification metadata against the variables declared by the
+    /// version of the template used for the locale
+    pub fn validate_metadata(
+        &self,
+        key: &TemplateKey,
+        locale: Option<&str>,
+        metadata: &Value,
+    ) -> Result<Vec<VariableError>, TemplateError> {
+        let engine = self
+            .engine(key)
+            .ok_or_else(|| TemplateError::NotFound(key.to_string()))?;
+
+        let variables = engine
+            .variables(&key.id, locale)
+            .ok_or_else(|| TemplateError::NotFound(key.to_string()))?;
+
+        Ok(validate_metadata(variables, metadata))
+    }
+
+    /// Recompiles the published version of every template, keeping the
+    /// current ones if the reload fails
+    pub async fn reload(&self) -> Result<usize, TemplateError> {
+        let _updates = self.updates.lock().await;
+
+        let engines = compile(
+            self.repository.as_ref(),
+            self.versions.as_ref(),
+            self.options,
+        )
+        .await?;
+        let count = engines.len();
+
+        *self.engines.write().unwrap() = Arc::new(engines);
+
+        Ok(count)
+    }
+
+    /// Engine of the published version of a template
+    fn engine(&self, key: &TemplateKey) -> Option<Arc<EmailTemplateEngine>> {
+        self.engines.read().unwrap().get(key).cloned()
+    }
+
+    /// Engine of a version that may not be published, compiled from the
+    /// version store the first time it's rendered
+    async fn pinned_engine(
+        &self,
+        key: &TemplateKey,
+        version: i64,
+    ) -> Result<Arc<EmailTemplateEngine>, TemplateError> {
+        if let Some(engine) = self.pinned.lock().unwrap().get(key, version) {
+            return Ok(engine);
+        }
+
+        let pinned = self.versions.find(key, version).await?;
+        let engine = Arc::new(version_engine(&pinned, self.options)?);
+
+        self.pinned
+            .lock()
+            .unwrap()
+            .insert(key, version, Arc::clone(&engine));
+
+        Ok(engine)
+    }
+
+    fn swap(&self, key: &TemplateKey, engine: Option<EmailTemplateEngine>) {
+        let mut engines = self.engines.write().unwrap();
+        let mut updated = HashMap::clone(&engines);
+
+        match engine {
+            Some(engine) => updated.insert(key.clone(), Arc::new(engine)),
+            None => updated.remove(key),
+        };
+
+        *engines = Arc::new(updated);
+    }
+
+    /// Compiles the version before moving the published pointer to it, so a
+    /// broken version is never published
+    async fn publish_version(
+        &self,
+        target: TemplateVersion,
+    ) -> Result<TemplateVersion, VersionError> {
+        let _updates = self.updates.lock().await;
+        let key = target.key.clone();
+        let engine = version_engine(&target, self.options)?;
+
+        let published = self.versions.publish(&key, target.version).await?;
+
+        self.swap(&key, Some(engine));
+
+        info!(
+            "Published version {} of email template {}",
+            published.version, key
+        );
+
+        Ok(published)
+    }
+
+    /// Reloads the templates whenever a file of the directory, or of its
+    /// partials and layouts, is changed, added or removed
+    pub fn watch(self: &Arc<Self>, templates_path: &str) -> Result<(), notify::Error> {
+        let (events, mut receiver) = mpsc::unbounded_channel();
+
+        let mut watcher: RecommendedWatcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if let Ok(event) = event {
+                    if !event.kind.is_access() {
+                        let _ = events.send(());
+                    }
+                }
+            })?;
+
+        watcher.watch(Path::new(templates_path), RecursiveMode::Recursive)?;
+
+        let cache = Arc::clone(self);
+
+        tokio::spawn(async move {
+            // The watcher stops when dropped, so the task owns it
+            let _watcher = watcher;
+
+            while receiver.recv().await.is_some() {
+                tokio::time::sleep(RELOAD_DEBOUNCE).await;
+                while receiver.try_recv().is_ok() {}
+
+                match cache.reload().await {
+                    Ok(count) => info!("Reloaded {} email templates", count),
+                    Err(err) => error!("Failed to reload email templates: {}", err),
+                }
+            }
+        });
+
+        info!("Watching {} for email template changes", templates_path);
+
+        Ok(())
+    }
+
+    /// Reloads the templates whenever the repository or the version store
+    /// changes, checking on every interval. Picks up the changes other
+    /// instances sharing the database make through the API.
+    pub fn poll(self: &Arc<Self>, interval: Duration) {
+        let cache = Arc::clone(self);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            // The first tick completes right away, and reads the revision
+            // the templates were loaded at
+            ticker.tick().await;
+            let mut current = cache.revision().await.ok();
+
+            loop {
+                ticker.tick().await;
+
+                let revision = match cache.revision().await {
+                    Ok(revision) => revision,
+                    Err(err) => {
+                        warn!("Failed to check email templates for changes: {}", err);
+                        continue;
+                    }
+                };
+
+                if current.as_ref() == Some(&revision) {
+                    continue;
+                }
+
+                // The revision is kept when the reload fails, so it is
+                // retried on the next tick
+                match cache.reload().await {
+                    Ok(count) => {
+                        info!("Reloaded {} email templates", count);
+                        current = Some(revision);
+                    }
+                    Err(err) => error!("Failed to reload email templates: {}", err),
+                }
+            }
+        });
+
+        info!("Polling email templates for changes every {:?}", interval);
+    }
+
+    async fn revision(&self) -> Result<(Option<String>, String), TemplateError> {
+        Ok((
+            self.repository.revision().await?,
+            self.versions.revision().await?,
+        ))
+    }
+}
+
+/// Compiles the published version of every template of the repository.
+/// The stored templates are compiled too, so broken ones fail the load even
+/// when a working version is published.
+async fn compile(
+    repository: &dyn EmailTemplateRepository,
+    versions: &dyn TemplateVersionStore,
+    options: TemplateOptions,
+) -> Result<HashMap<TemplateKey, Arc<EmailTemplateEngine>>, TemplateError> {
+    let partials = repository.list_partials().await?;
+    let layouts = repository.list_layouts().await?;
+
+    let mut failures = register_shared(
+        &mut EmailTemplateEngine::new(options.strict),
+        &partials,
+        &layouts,
+    );
+    let mut engines = HashMap::new();
+
+    for (key, locales) in repository.list_with_locales().await? {
+        let stored = read_snapshot(repository, &key, locales, &partials, &layouts)
+            .await
+            .and_then(|snapshot| {
+                snapshot_engine(&snapshot, options)?;
+
+                Ok(snapshot)
+            });
+
+        // A broken stored template keeps its published version, if any
+        let stored = match stored {
+            Ok(snapshot) => Some(snapshot),
+            Err(err) => {
+                error!("Broken email template {}: {}", key, err);
+                failures += 1;
+                None
+            }
+        };
+
+        let published = resolve_published(versions, &key, stored.as_ref(), options)
+            .await
+            .and_then(|published| {
+                published
+                    .map(|published| version_engine(&published, options))
+                    .transpose()
+                    .map_err(VersionError::from)
+            });
+
+        match published {
+            Ok(Some(engine)) => {
+                engines.insert(key, Arc::new(engine));
+            }
+            Ok(None) => {}
+            Err(err) => {
+                error!("Broken email template {}: {}", key, err);
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        if !options.warn_only {
+            return Err(TemplateError::ValidationFailed(failures));
+        }
+
+        warn!("Skipped {} broken email templates", failures);
+    }
+
+    Ok(engines)
+}
+
+/// The version of a template to render. Stored templates are recorded and
+/// published when they have no version yet, and when they changed since the
+/// latest version if `record_changes` is set. Otherwise saving through the
+/// API publishes them.
+async fn resolve_published(
+    versions: &dyn TemplateVersionStore,
+    key: &TemplateKey,
+    stored: Option<&TemplateSnapshot>,
+    options: TemplateOptions,
+) -> Result<Option<TemplateVersion>, VersionError> {
+    let published = versions.published(key).await?;
+
+    let Some(stored) = stored else {
+        return Ok(published);
+    };
+
+    // Compared with the latest version rather than the published one, so a
+    // rollback isn't undone by the templates it rolled back from
+    let changed = !versions
+        .latest(key)
+        .await?
+        .is_some_and(|latest| latest.snapshot.matches(stored));
+
+    match published {
+        Some(published) if !changed => Ok(Some(published)),
+        Some(published) if !options.record_changes => {
+            warn!(
+                "Email template {} changed since version {}, save it through the API to publish it",
+                key, published.version
+            );
+
+            Ok(Some(published))
+        }
+        _ => {
+            let recorded = versions.record(key, stored).await?;
+
+            info!(
+                "Published version {} of email template {}",
+                recorded.version, key
+            );
+
+            Ok(Some(recorded))
+        }
+    }
+}
+
+/// The stored template and its translations, with the partials and layouts
+/// they use
+async fn read_snapshot(
+    repository: &dyn EmailTemplateRepository,
+    key: &TemplateKey,
+    locales: Vec<String>,
+    partials: &[TemplatePartial],
+    layouts: &[TemplatePartial],
+) -> Result<TemplateSnapshot, TemplateError> {
+    let mut templates = vec![repository.find_by_id(key).await?];
+
+    for locale in locales {
+        templates.push(repository.find_localized(key, &locale).await?);
+    }
+
+    let layouts: Vec<TemplatePartial> = layouts
+        .iter()
+        .filter(|layout| {
+            templates
+                .iter()
+                .any(|template| template.layout.as_deref() == Some(layout.name.as_str()))
+        })
+        .cloned()
+        .collect();
+
+    // Partials can include other partials, so references are followed
+    // through the partials found along the way
+    let mut pending: Vec<&str> = templates
+        .iter()
+        .flat_map(|template| {
+            [
+                Some(template.subject.as_str()),
+                Some(template.body.as_str()),
+                template.text_body.as_deref(),
+                template.preheader.as_deref(),
+            ]
+        })
+        .flatten()
+        .chain(layouts.iter().map(|layout| layout.source.as_str()))
+        .collect();
+    let mut referenced = BTreeSet::new();
+
+    while let Some(source) = pending.pop() {
+        for reference in PARTIAL_REFERENCE.captures_iter(source) {
+            let name = &reference[1];
+
+            if !referenced.insert(name.to_string()) {
+                continue;
+            }
+
+            if let Some(partial) = partials.iter().find(|partial| partial.name == name) {
+                pending.push(&partial.source);
+            }
+        }
+    }
+
+    let partials = partials
+        .iter()
+        .filter(|partial| referenced.contains(&partial.name))
+        .cloned()
+        .collect();
+
+    Ok(TemplateSnapshot {
+        templates,
+        partials,
+        layouts,
+    })
+}
+
+/// An engine with the current partials and layouts of the repository, and
+/// how many of them are broken
+async fn shared_engine(
+    repository: &dyn EmailTemplateRepository,
+    options: TemplateOptions,
+) -> Result<(EmailTemplateEngine, usize), TemplateError> {
+    let mut engine = EmailTemplateEngine::new(options.strict);
+
+    let failures = register_shared(
+        &mut engine,
+        &repository.list_partials().await?,
+        &repository.list_layouts().await?,
+    );
+
+    Ok((engine, failures))
+}
+
+/// Registers the partials and layouts, logging and counting the broken ones
+fn register_shared(
+    engine: &mut EmailTemplateEngine,
+    partials: &[TemplatePartial],
+    layouts: &[TemplatePartial],
+) -> usize {
+    let mut failures = 0;
+
+    // Templates can only use the partials and layouts registered before them
+    for partial in partials {
+        if let Err(err) = engine.register_partial(&partial.name, &partial.source) {
+            error!("Broken email partial {}: {}", partial.name, err);
+            failures += 1;
+        }
+    }
+
+    for layout in layouts {
+        if let Err(err) = engine.register_layout(&layout.name, &layout.source) {
+            error!("Broken email layout {}: {}", layout.name, err);
+            failures += 1;
+        }
+    }
+
+    failures
+}
+
+/// An engine with only the templates, partials and layouts of the snapshot
+fn snapshot_engine(
+    snapshot: &TemplateSnapshot,
+    options: TemplateOptions,
+) -> Result<EmailTemplateEngine, TemplateError> {
+    let mut engine = EmailTemplateEngine::new(options.strict);
+
+    for partial in &snapshot.partials {
+        engine.register_partial(&partial.name, &partial.source)?;
+    }
+
+    for layout in &snapshot.layouts {
+        engine.register_layout(&layout.name, &layout.source)?;
+    }
+
+    for template in &snapshot.templates {
+        engine.register(template)?;
+    }
+
+    Ok(engine)
+}
+
+fn version_engine(
+    version: &TemplateVersion,
+    options: TemplateOptions,
+) -> Result<EmailTemplateEngine, TemplateError> {
+    let mut engine = snapshot_engine(&version.snapshot, options)?;
+
+    engine.set_version(&version.key.id, version.version);
+
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pinned_engines_evict_the_oldest_version_once_full() {
+        let mut pinned = PinnedEngines::default();
+        let key = TemplateKey::shared("welcome");
+
+        for version in 1..=PINNED_ENGINES as i64 + 1 {
+            pinned.insert(&key, version, Arc::new(EmailTemplateEngine::new(false)));
+        }
+
+        assert!(pinned.get(&key, 1).is_none());
+        assert!(pinned.get(&key, 2).is_some());
+        assert!(pinned.get(&key, PINNED_ENGINES as i64 + 1).is_some());
+        assert_eq!(pinned.engines.len(), PINNED_ENGINES);
+    }
+
+    #[test]
+    fn pinned_engines_are_cached_per_template_and_version() {
+        let mut pinned = PinnedEngines::default();
+        let engine = Arc::new(EmailTemplateEngine::new(false));
+
+        pinned.insert(&TemplateKey::shared("welcome"), 1, Arc::clone(&engine));
+        pinned.insert(&TemplateKey::shared("welcome"), 1, Arc::clone(&engine));
+
+        assert!(Arc::ptr_eq(
+            &pinned.get(&TemplateKey::shared("welcome"), 1).unwrap(),
+            &engine
+        ));
+        assert!(pinned.get(&TemplateKey::shared("welcome"), 2).is_none());
+        assert!(pinned
+            .get(&TemplateKey::owned("acme", "welcome"), 1)
+            .is_none());
+        assert_eq!(pinned.order.len(), 1);
+    }
+}
@@ -1,35 +1,470 @@
-use handlebars::Handlebars;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use handlebars::{Handlebars, RenderError, RenderErrorReason, Template};
 use serde_json::Value;
 
-use super::template::{EmailTemplate, TemplateError};
+use super::{
+    format::{escape_markdown, TemplateFormat},
+    helpers::register_helpers,
+    locale::{fallback_chain, normalize_locale, LOCALE_KEY},
+    processing::{process_html, HtmlProcessing},
+    template::{EmailTemplate, TemplateError},
+    text::html_to_text,
+    variables::TemplateVariable,
+};
+
+/// An email template rendered with the notification metadata. The text part
+/// comes from the template's `text_body`, or is generated from the HTML.
+#[derive(Debug, Clone)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Metadata key the engine sets to the HTML body when wrapping it in its
+/// layout
+const LAYOUT_BODY_KEY: &str = "_body";
+
+/// Handlebars registries for the HTML body, the Markdown body and the plain
+/// text fields
+struct Registries {
+    html: Handlebars<'static>,
+    markdown: Handlebars<'static>,
+    plain: Handlebars<'static>,
+}
+
+impl Registries {
+    fn new(strict: bool) -> Self {
+        let mut html = Handlebars::new();
+        let mut markdown = Handlebars::new();
+        let mut plain = Handlebars::new();
+
+        // MJML bodies use the HTML registry, since HTML escaping also
+        // escapes XML
+        markdown.register_escape_fn(escape_markdown);
+
+        // Subjects and text bodies are not HTML, so values must not be
+        // HTML-escaped
+        plain.register_escape_fn(handlebars::no_escape);
+
+        for handlebars in [&mut html, &mut markdown, &mut plain] {
+            handlebars.set_strict_mode(strict);
+            register_helpers(handlebars);
+        }
+
+        Self {
+            html,
+            markdown,
+            plain,
+        }
+    }
 
+    /// Registry escaping the values substituted in a body of the format
+    fn body(&self, format: TemplateFormat) -> &Handlebars<'static> {
+        match format {
+            TemplateFormat::Markdown => &self.markdown,
+            TemplateFormat::Html | TemplateFormat::Mjml => &self.html,
+        }
+    }
+
+    fn body_mut(&mut self, format: TemplateFormat) -> &mut Handlebars<'static> {
+        match format {
+            TemplateFormat::Markdown => &mut self.markdown,
+            TemplateFormat::Html | TemplateFormat::Mjml => &mut self.html,
+        }
+    }
+}
+
+struct RegisteredTemplate {
+    strict: bool,
+    variables: Vec<TemplateVariable>,
+    format: TemplateFormat,
+    layout: Option<String>,
+    processing: HtmlProcessing,
+}
+
+/// Renders email templates precompiled into its registries with `register`.
+/// Strict templates fail on missing variables instead of rendering them as
+/// empty strings.
+///
+/// Partials and layouts are shared by every template, so they must be
+/// registered before the templates that use them. Translations of a template
+/// are registered alongside its default version.
 pub struct EmailTemplateEngine {
-    handlebars: Handlebars<'static>,
+    strict: Registries,
+    lenient: Registries,
+    strict_by_default: bool,
+    templates: HashMap<String, RegisteredTemplate>,
+    layouts: HashSet<String>,
+    versions: HashMap<String, i64>,
 }
 
 impl Default for EmailTemplateEngine {
     fn default() -> Self {
-        Self::new()
+        Self::new(false)
     }
 }
 
 impl EmailTemplateEngine {
-    pub fn new() -> Self {
+    /// `strict_by_default` applies to templates that don't set `strict`
+    pub fn new(strict_by_default: bool) -> Self {
         Self {
-            handlebars: Handlebars::new(),
+            strict: Registries::new(true),
+            lenient: Registries::new(false),
+            strict_by_default,
+            templates: HashMap::new(),
+            layouts: HashSet::new(),
+            versions: HashMap::new(),
         }
     }
 
-    pub fn render(
+    /// Registers a partial, used in templates as `{{> name}}`
+    pub fn register_partial(&mut self, name: &str, source: &str) -> Result<(), TemplateError> {
+        let partial = compile(name, "partial", source)?;
+
+        for registries in [&mut self.strict, &mut self.lenient] {
+            registries.html.register_template(name, partial.clone());
+            registries.markdown.register_template(name, partial.clone());
+            registries.plain.register_template(name, partial.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Registers a layout, which wraps the HTML body of the templates that
+    /// set it and renders it with `{{> @partial-block}}`
+    pub fn register_layout(&mut self, name: &str, source: &str) -> Result<(), TemplateError> {
+        let layout = compile(name, "layout", source)?;
+
+        // Renders the layout around a body already compiled to HTML, which
+        // is passed in the metadata
+        let wrapper = compile(
+            name,
+            "layout",
+            &format!(
+                "{{{{#> {}}}}}{{{{{{@root.{}}}}}}}{{{{/{}}}}}",
+                layout_name(name),
+                LAYOUT_BODY_KEY,
+                layout_name(name)
+            ),
+        )?;
+
+        for registries in [&mut self.strict, &mut self.lenient] {
+            registries
+                .html
+                .register_template(&layout_name(name), layout.clone());
+            registries
+                .html
+                .register_template(&wrapper_name(name), wrapper.clone());
+        }
+
+        self.layouts.insert(name.to_string());
+
+        Ok(())
+    }
+
+    /// Compiles every field of the template, replacing any template
+    /// registered with the same ID and locale. Nothing is registered if a
+    /// field fails to compile.
+    pub fn register(&mut self, template: &EmailTemplate) -> Result<(), TemplateError> {
+        let id = &template_key(&template.id, template.locale.as_deref());
+
+        self.check_layout(template)?;
+
+        template
+            .format
+            .validate(&template.body)
+            .map_err(|message| TemplateError::InvalidTemplate {
+                id: id.to_string(),
+                location: "body".to_string(),
+                message,
+            })?;
+
+        let subject = compile(id, "subject", &template.subject)?;
+        let body = compile(id, "body", &template.body)?;
+        let text = template
+            .text_body
+            .as_ref()
+            .map(|text_body| compile(id, "text_body", text_body))
+            .transpose()?;
+        let preheader = template
+            .preheader
+            .as_ref()
+            .map(|preheader| compile(id, "preheader", preheader))
+            .transpose()?;
+
+        self.unregister(id);
+
+        let strict = self.is_strict(template);
+        let registries = self.registries_mut(strict);
+
+        registries
+            .plain
+            .register_template(&subject_name(id), subject);
+        registries
+            .body_mut(template.format)
+            .register_template(&body_name(id), body);
+
+        if let Some(text) = text {
+            registries.plain.register_template(&text_name(id), text);
+        }
+
+        if let Some(preheader) = preheader {
+            registries
+                .html
+                .register_template(&preheader_name(id), preheader);
+        }
+
+        self.templates.insert(
+            id.to_string(),
+            RegisteredTemplate {
+                strict,
+                variables: template.variables.clone(),
+                format: template.format,
+                layout: template.layout.clone(),
+                processing: template.processing,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Records which stored version of a template is registered
+    pub fn set_version(&mut self, id: &str, version: i64) {
+        self.versions.insert(id.to_string(), version);
+    }
+
+    pub fn version(&self, id: &str) -> Option<i64> {
+        self.versions.get(id).copied()
+    }
+
+    /// Variables declared by the version of a template used for the locale
+    pub fn variables(&self, id: &str, locale: Option<&str>) -> Option<&[TemplateVariable]> {
+        let key = self.resolve(id, locale)?;
+
+        self.templates
+            .get(&key)
+            .map(|template| template.variables.as_slice())
+    }
+
+    /// Renders a template previously compiled with `register`, in the most
+    /// specific version registered for the locale. Locale-aware helpers
+    /// format for the requested locale.
+    pub fn render_registered(
         &self,
-        template: &EmailTemplate,
+        id: &str,
+        locale: Option<&str>,
         metadata: &Value,
-    ) -> Result<String, TemplateError> {
-        let email_content = self
-            .handlebars
-            .render_template(&template.body, metadata)
-            .map_err(|err| TemplateError::RenderError(err.to_string()))?;
+    ) -> Result<RenderedEmail, TemplateError> {
+        let key = self
+            .resolve(id, locale)
+            .ok_or_else(|| TemplateError::NotFound(id.to_string()))?;
+        let template = &self.templates[&key];
+        let id = key.as_str();
+
+        let locale = locale.map(normalize_locale);
+        let metadata = &*with_locale(metadata, locale.as_deref());
+
+        let registries = self.registries(template.strict);
+
+        let subject = registries
+            .plain
+            .render(&subject_name(id), metadata)
+            .map_err(|err| render_error(id, err))?;
+
+        let body = registries
+            .body(template.format)
+            .render(&body_name(id), metadata)
+            .map_err(|err| render_error(id, err))?;
+        let html = wrap_in_layout(
+            registries,
+            id,
+            template.layout.as_deref(),
+            template.format.to_html(&body)?,
+            metadata,
+        )?;
+
+        let text = if registries.plain.has_template(&text_name(id)) {
+            registries
+                .plain
+                .render(&text_name(id), metadata)
+                .map_err(|err| render_error(id, err))?
+        } else {
+            html_to_text(&html)
+        };
+
+        let preheader = if registries.html.has_template(&preheader_name(id)) {
+            let preheader = registries
+                .html
+                .render(&preheader_name(id), metadata)
+                .map_err(|err| render_error(id, err))?;
+
+            Some(preheader)
+        } else {
+            None
+        };
+
+        Ok(RenderedEmail {
+            subject,
+            html: process_html(&html, &template.processing, preheader.as_deref())?,
+            text,
+        })
+    }
+
+    fn check_layout(&self, template: &EmailTemplate) -> Result<(), TemplateError> {
+        match &template.layout {
+            Some(layout) if !self.layouts.contains(layout) => Err(TemplateError::InvalidTemplate {
+                id: template.id.clone(),
+                location: "layout".to_string(),
+                message: format!("Unknown layout {}", layout),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Key of the most specific version of a template registered for the
+    /// locale, falling back to the default version
+    fn resolve(&self, id: &str, locale: Option<&str>) -> Option<String> {
+        let locale = locale.map(normalize_locale);
+
+        locale
+            .as_deref()
+            .map(fallback_chain)
+            .unwrap_or_default()
+            .iter()
+            .map(|candidate| template_key(id, Some(candidate)))
+            .chain([id.to_string()])
+            .find(|key| self.templates.contains_key(key))
+    }
+
+    fn is_strict(&self, template: &EmailTemplate) -> bool {
+        template.strict.unwrap_or(self.strict_by_default)
+    }
+
+    fn registries(&self, strict: bool) -> &Registries {
+        if strict {
+            &self.strict
+        } else {
+            &self.lenient
+        }
+    }
+
+    fn registries_mut(&mut self, strict: bool) -> &mut Registries {
+        if strict {
+            &mut self.strict
+        } else {
+            &mut self.lenient
+        }
+    }
+
+    fn unregister(&mut self, id: &str) {
+        for registries in [&mut self.strict, &mut self.lenient] {
+            registries.plain.unregister_template(&subject_name(id));
+            registries.html.unregister_template(&body_name(id));
+            registries.markdown.unregister_template(&body_name(id));
+            registries.plain.unregister_template(&text_name(id));
+            registries.html.unregister_template(&preheader_name(id));
+        }
+
+        self.templates.remove(id);
+    }
+}
+
+/// Translations are registered as `{id}.{locale}`, which can't clash with
+/// template IDs since they have no dots
+fn template_key(id: &str, locale: Option<&str>) -> String {
+    match locale {
+        Some(locale) => format!("{}.{}", id, normalize_locale(locale)),
+        None => id.to_string(),
+    }
+}
+
+/// Exposes the locale to the helpers through the metadata, unless the
+/// metadata already uses the key
+fn with_locale<'a>(metadata: &'a Value, locale: Option<&str>) -> Cow<'a, Value> {
+    match (metadata, locale) {
+        (Value::Object(fields), Some(locale)) if !fields.contains_key(LOCALE_KEY) => {
+            let mut fields = fields.clone();
+            fields.insert(LOCALE_KEY.to_string(), Value::String(locale.to_string()));
+
+            Cow::Owned(Value::Object(fields))
+        }
+        _ => Cow::Borrowed(metadata),
+    }
+}
+
+/// Renders the layout around the body, once the body is compiled to HTML so
+/// the layout's markup isn't compiled along with it
+fn wrap_in_layout(
+    registries: &Registries,
+    id: &str,
+    layout: Option<&str>,
+    body: String,
+    metadata: &Value,
+) -> Result<String, TemplateError> {
+    let Some(layout) = layout else {
+        return Ok(body);
+    };
+
+    let mut fields = match metadata {
+        Value::Object(fields) => fields.clone(),
+        _ => Default::default(),
+    };
+    fields.insert(LAYOUT_BODY_KEY.to_string(), Value::String(body));
+
+    registries
+        .html
+        .render(&wrapper_name(layout), &Value::Object(fields))
+        .map_err(|err| render_error(id, err))
+}
+
+fn layout_name(name: &str) -> String {
+    format!("layouts/{}", name)
+}
+
+fn wrapper_name(name: &str) -> String {
+    format!("layouts/{}.wrapper", name)
+}
+
+fn subject_name(id: &str) -> String {
+    format!("{}.subject", id)
+}
+
+fn body_name(id: &str) -> String {
+    format!("{}.body", id)
+}
+
+fn text_name(id: &str) -> String {
+    format!("{}.text", id)
+}
+
+fn preheader_name(id: &str) -> String {
+    format!("{}.preheader", id)
+}
+
+fn compile(id: &str, field: &str, source: &str) -> Result<Template, TemplateError> {
+    Template::compile(source).map_err(|err| {
+        let location = match err.pos() {
+            Some((line, column)) => format!("{} line {}, column {}", field, line, column),
+            None => field.to_string(),
+        };
+
+        TemplateError::InvalidTemplate {
+            id: id.to_string(),
+            location,
+            message: err.reason().to_string(),
+        }
+    })
+}
 
-        Ok(email_content)
+fn render_error(id: &str, err: RenderError) -> TemplateError {
+    match err.reason() {
+        RenderErrorReason::MissingVariable(name) => TemplateError::MissingVariable {
+            name: name.clone().unwrap_or_default(),
+            template_id: id.to_string(),
+        },
+        _ => TemplateError::RenderError(err.to_string()),
     }
 }
@@ -0,0 +1,99 @@
+use mrml::prelude::render::RenderOptions;
+use pulldown_cmark::{html, Options, Parser};
+use serde::{Deserialize, Serialize};
+
+use super::template::TemplateError;
+
+/// Markup the body of a template is written in. Bodies are compiled to HTML
+/// after the Handlebars substitution, which escapes values for the markup so
+/// they can't inject any.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateFormat {
+    #[default]
+    Html,
+    Markdown,
+    Mjml,
+}
+
+impl TemplateFormat {
+    pub fn to_html(self, body: &str) -> Result<String, TemplateError> {
+        match self {
+            TemplateFormat::Html => Ok(body.to_string()),
+            TemplateFormat::Markdown => Ok(markdown_to_html(body)),
+            TemplateFormat::Mjml => mjml_to_html(body),
+        }
+    }
+
+    /// Checks that the body source is valid markup, returning the parser
+    /// error otherwise
+    pub fn validate(self, body: &str) -> Result<(), String> {
+        match self {
+            TemplateFormat::Html | TemplateFormat::Markdown => Ok(()),
+            TemplateFormat::Mjml => mrml::parse(body).map(|_| ()).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// Replaces the ASCII punctuation of a value with numeric character
+/// references. Markdown renders them as literal text, and they stay escaped
+/// in raw HTML blocks too, which Markdown passes through untouched.
+pub fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push_str(&format!("&#{};", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+
+    escaped
+}
+
+fn markdown_to_html(body: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let mut output = String::with_capacity(body.len() * 3 / 2);
+
+    html::push_html(&mut output, Parser::new_ext(body, options));
+
+    output
+}
+
+fn mjml_to_html(body: &str) -> Result<String, TemplateError> {
+    let parsed = mrml::parse(body)
+        .map_err(|err| TemplateError::RenderError(format!("Invalid MJML: {}", err)))?;
+
+    parsed
+        .element
+        .render(&RenderOptions::default())
+        .map_err(|err| TemplateError::RenderError(format!("Failed to render MJML: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_values_render_as_literal_markdown_text() {
+        let body = format!("Hi {}", escape_markdown("*Ana* [link](http://x) <b>"));
+
+        assert_eq!(
+            TemplateFormat::Markdown.to_html(&body).unwrap(),
+            "<p>Hi *Ana* [link](http://x) &lt;b&gt;</p>\n"
+        );
+    }
+
+    #[test]
+    fn escaped_values_stay_escaped_in_raw_html_blocks() {
+        let body = format!(
+            "<div>\n{}\n</div>",
+            escape_markdown("<script>alert(1)</script>")
+        );
+        let html = TemplateFormat::Markdown.to_html(&body).unwrap();
+
+        assert!(!html.contains("<script>"), "{}", html);
+        assert!(html.contains("&#60;script&#62;"), "{}", html);
+    }
+}
@@ -0,0 +1,437 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use handlebars::{
+    handlebars_helper, Context, Handlebars, Helper, HelperDef, RenderContext, RenderError,
+    RenderErrorReason, ScopedJson,
+};
+use serde_json::Value;
+
+use super::locale::{LocaleFormat, SymbolPosition, LOCALE_KEY};
+
+/// Registers the helpers available to every email template
+pub fn register_helpers(handlebars: &mut Handlebars<'static>) {
+    handlebars.register_helper("date", Box::new(DateHelper));
+    handlebars.register_helper("currency", Box::new(CurrencyHelper));
+    handlebars.register_helper("number", Box::new(NumberHelper));
+    handlebars.register_helper("pluralize", Box::new(pluralize_helper));
+    handlebars.register_helper("uppercase", Box::new(uppercase_helper));
+    handlebars.register_helper("default", Box::new(DefaultHelper));
+}
+
+/// `{{date sent_at}}` or `{{date sent_at format="%d %B %Y"}}`, from an RFC
+/// 3339 string, a YYYY-MM-DD date or a Unix timestamp in seconds. Month and
+/// day names and the default format follow the locale.
+struct DateHelper;
+
+impl HelperDef for DateHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let Some(value) = param(h, r, "date")? else {
+            return Ok(empty());
+        };
+        let locale = locale_format(h, ctx);
+        let format = hash_str(h, "format").unwrap_or(locale.date_format);
+
+        let Some(datetime) = parse_date(value) else {
+            return Err(RenderErrorReason::Other(format!("Invalid date: {}", value)).into());
+        };
+
+        let mut formatted = String::new();
+
+        // Invalid format strings surface as fmt errors instead of panicking
+        if write!(
+            formatted,
+            "{}",
+            datetime.format_localized(format, locale.chrono_locale)
+        )
+        .is_err()
+        {
+            return Err(
+                RenderErrorReason::Other(format!("Invalid date format: {}", format)).into(),
+            );
+        }
+
+        Ok(ScopedJson::Derived(Value::String(formatted)))
+    }
+}
+
+/// `{{currency total}}` or `{{currency total code="EUR"}}`, with the
+/// separators and symbol position of the locale
+struct CurrencyHelper;
+
+impl HelperDef for CurrencyHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let Some(amount) = number_param(h, r, "currency")? else {
+            return Ok(empty());
+        };
+        let code = hash_str(h, "code").unwrap_or("USD");
+
+        Ok(ScopedJson::Derived(Value::String(format_currency(
+            amount,
+            code,
+            &locale_format(h, ctx),
+        ))))
+    }
+}
+
+/// `{{number total}}` or `{{number total decimals=1}}`, with the separators
+/// of the locale. Integers have no decimals by default, other numbers two.
+struct NumberHelper;
+
+impl HelperDef for NumberHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let Some(value) = number_param(h, r, "number")? else {
+            return Ok(empty());
+        };
+
+        let decimals = h
+            .hash_get("decimals")
+            .and_then(|decimals| decimals.value().as_u64())
+            .map(|decimals| decimals.min(10) as usize)
+            .unwrap_or(if value.fract() == 0.0 { 0 } else { 2 });
+
+        Ok(ScopedJson::Derived(Value::String(
+            locale_format(h, ctx).format_number(value, decimals),
+        )))
+    }
+}
+
+// {{count}} {{pluralize count "item"}} or {{pluralize count "child" plural="children"}}
+handlebars_helper!(pluralize_helper: |count: f64, singular: str, { plural: str = "" }| {
+    if count == 1.0 {
+        singular.to_string()
+    } else if plural.is_empty() {
+        format!("{}s", singular)
+    } else {
+        plural.to_string()
+    }
+});
+
+handlebars_helper!(uppercase_helper: |value: Json| display(value).to_uppercase());
+
+/// `{{default nickname "friend"}}` renders the fallback when the value is
+/// missing, null or empty. Implemented by hand since the helper macro rejects
+/// missing parameters in strict mode, which is exactly when this is needed.
+struct DefaultHelper;
+
+impl HelperDef for DefaultHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let fallback = h
+            .param(1)
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("default", 1))?;
+
+        let value = h
+            .param(0)
+            .filter(|param| !param.is_value_missing())
+            .map(|param| param.value())
+            .filter(|value| !is_empty(value))
+            .unwrap_or(fallback.value());
+
+        Ok(ScopedJson::Derived(value.clone()))
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(string) => string.is_empty(),
+        Value::Array(array) => array.is_empty(),
+        _ => false,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn parse_date(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(string) => DateTime::parse_from_rfc3339(string)
+            .map(|datetime| datetime.with_timezone(&Utc))
+            .ok()
+            .or_else(|| {
+                NaiveDate::parse_from_str(string, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+                    .map(|datetime| datetime.and_utc())
+            }),
+        Value::Number(number) => number
+            .as_i64()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0)),
+        _ => None,
+    }
+}
+
+fn format_currency(amount: f64, code: &str, locale: &LocaleFormat) -> String {
+    let code = code.to_uppercase();
+
+    let (symbol, decimals) = match code.as_str() {
+        "USD" => (Some("$"), 2),
+        "EUR" => (Some("€"), 2),
+        "GBP" => (Some("£"), 2),
+        "BRL" => (Some("R$"), 2),
+        "JPY" => (Some("¥"), 0),
+        _ => (None, 2),
+    };
+
+    // The sign goes before the symbol, and only when the amount doesn't
+    // round to zero
+    let formatted = locale.format_number(amount, decimals);
+    let (sign, number) = match formatted.strip_prefix('-') {
+        Some(number) => ("-", number),
+        None => ("", formatted.as_str()),
+    };
+
+    match (symbol, locale.currency_symbol) {
+        (Some(symbol), SymbolPosition::Before) => format!("{}{}{}", sign, symbol, number),
+        (Some(symbol), SymbolPosition::BeforeSpaced) => {
+            format!("{}{} {}", sign, symbol, number)
+        }
+        (Some(symbol), SymbolPosition::After) => format!("{}{} {}", sign, number, symbol),
+        (None, _) => format!("{}{} {}", sign, number, code),
+    }
+}
+
+/// The locale set with `locale="pt-BR"`, or else the one the template is
+/// rendered in
+fn locale_format(h: &Helper, ctx: &Context) -> LocaleFormat {
+    let locale = hash_str(h, "locale").or_else(|| ctx.data().get(LOCALE_KEY)?.as_str());
+
+    LocaleFormat::for_locale(locale)
+}
+
+fn hash_str<'a>(h: &'a Helper, name: &str) -> Option<&'a str> {
+    h.hash_get(name)?.value().as_str()
+}
+
+/// The value a helper formats. Like variables outside helpers, a missing
+/// value fails in strict mode and renders empty otherwise.
+fn param<'a>(
+    h: &'a Helper,
+    r: &Handlebars,
+    helper: &'static str,
+) -> Result<Option<&'a Value>, RenderError> {
+    let param = h
+        .param(0)
+        .ok_or(RenderErrorReason::ParamNotFoundForIndex(helper, 0))?;
+
+    if !param.is_value_missing() && !param.value().is_null() {
+        Ok(Some(param.value()))
+    } else if r.strict_mode() {
+        Err(RenderErrorReason::ParamNotFoundForIndex(helper, 0).into())
+    } else {
+        Ok(None)
+    }
+}
+
+fn number_param(
+    h: &Helper,
+    r: &Handlebars,
+    helper: &'static str,
+) -> Result<Option<f64>, RenderError> {
+    let Some(value) = param(h, r, helper)? else {
+        return Ok(None);
+    };
+
+    value.as_f64().map(Some).ok_or_else(|| {
+        RenderErrorReason::ParamTypeMismatchForName(helper, "0".to_string(), "f64".to_string())
+            .into()
+    })
+}
+
+fn empty<'rc>() -> ScopedJson<'rc> {
+    ScopedJson::Derived(Value::String(String::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn render(strict: bool, template: &str, data: Value) -> Result<String, RenderError> {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(strict);
+        handlebars.register_escape_fn(handlebars::no_escape);
+        register_helpers(&mut handlebars);
+
+        handlebars.render_template(template, &data)
+    }
+
+    #[test]
+    fn date_parses_rfc_3339_dates_and_timestamps() {
+        let template = r#"{{date sent_at format="%Y-%m-%d %H:%M"}}"#;
+
+        for sent_at in [
+            json!("2024-03-05T14:30:00Z"),
+            json!("2024-03-05T11:30:00-03:00"),
+            json!(1709649000),
+        ] {
+            assert_eq!(
+                render(false, template, json!({ "sent_at": sent_at })).unwrap(),
+                "2024-03-05 14:30"
+            );
+        }
+
+        assert_eq!(
+            render(false, template, json!({ "sent_at": "2024-03-05" })).unwrap(),
+            "2024-03-05 00:00"
+        );
+    }
+
+    #[test]
+    fn date_formats_for_the_locale() {
+        let data = json!({ "day": "2024-03-05", LOCALE_KEY: "pt-BR" });
+
+        assert_eq!(
+            render(false, r#"{{date day format="%d de %B de %Y"}}"#, data).unwrap(),
+            "05 de março de 2024"
+        );
+    }
+
+    #[test]
+    fn date_rejects_unparseable_dates_and_formats() {
+        let data = json!({ "day": "2024-03-05", "bad": "yesterday" });
+
+        assert!(render(false, r#"{{date day format="%Q"}}"#, data.clone()).is_err());
+        assert!(render(false, "{{date bad}}", data).is_err());
+    }
+
+    #[test]
+    fn date_of_a_missing_value_is_empty_unless_strict() {
+        assert_eq!(
+            render(false, "[{{date missing}}]", json!({})).unwrap(),
+            "[]"
+        );
+        assert!(render(true, "{{date missing}}", json!({})).is_err());
+    }
+
+    #[test]
+    fn pluralize_uses_the_singular_only_for_one() {
+        let template =
+            r#"{{pluralize count "item"}} {{pluralize count "child" plural="children"}}"#;
+
+        assert_eq!(
+            render(false, template, json!({ "count": 1 })).unwrap(),
+            "item child"
+        );
+        assert_eq!(
+            render(false, template, json!({ "count": 0 })).unwrap(),
+            "items children"
+        );
+        assert_eq!(
+            render(false, template, json!({ "count": 2.5 })).unwrap(),
+            "items children"
+        );
+    }
+
+    #[test]
+    fn default_renders_the_fallback_for_missing_values_in_strict_mode() {
+        let template = r#"Hi {{default nickname "friend"}}"#;
+
+        assert_eq!(render(true, template, json!({})).unwrap(), "Hi friend");
+        assert_eq!(
+            render(true, template, json!({ "nickname": null })).unwrap(),
+            "Hi friend"
+        );
+        assert_eq!(
+            render(true, template, json!({ "nickname": "" })).unwrap(),
+            "Hi friend"
+        );
+        assert_eq!(
+            render(true, template, json!({ "nickname": "Ana" })).unwrap(),
+            "Hi Ana"
+        );
+    }
+
+    #[test]
+    fn number_defaults_to_two_decimals_for_fractions_only() {
+        let data = json!({ "whole": 1234, "fraction": 1234.5 });
+
+        assert_eq!(
+            render(false, "{{number whole}} {{number fraction}}", data.clone()).unwrap(),
+            "1,234 1,234.50"
+        );
+        assert_eq!(
+            render(
+                false,
+                "{{number whole decimals=2}} {{number fraction decimals=1}}",
+                data
+            )
+            .unwrap(),
+            "1,234.00 1,234.5"
+        );
+    }
+
+    #[test]
+    fn number_uses_the_separators_of_the_locale() {
+        assert_eq!(
+            render(
+                false,
+                r#"{{number total locale="pt-BR"}}"#,
+                json!({ "total": 1234.5 })
+            )
+            .unwrap(),
+            "1.234,50"
+        );
+    }
+
+    #[test]
+    fn format_currency_places_the_symbol_for_the_locale() {
+        let english = LocaleFormat::for_locale(Some("en-US"));
+        let brazilian = LocaleFormat::for_locale(Some("pt-BR"));
+        let german = LocaleFormat::for_locale(Some("de"));
+
+        assert_eq!(format_currency(1234.5, "USD", &english), "$1,234.50");
+        assert_eq!(format_currency(1234.5, "brl", &brazilian), "R$ 1.234,50");
+        assert_eq!(format_currency(1234.5, "EUR", &german), "1.234,50 €");
+    }
+
+    #[test]
+    fn format_currency_follows_the_currency_decimals() {
+        let english = LocaleFormat::for_locale(Some("en-US"));
+
+        assert_eq!(format_currency(1500.0, "JPY", &english), "¥1,500");
+        assert_eq!(format_currency(-20.0, "USD", &english), "-$20.00");
+        assert_eq!(format_currency(20.0, "CHF", &english), "20.00 CHF");
+    }
+
+    #[test]
+    fn format_currency_never_shows_negative_zero() {
+        let english = LocaleFormat::for_locale(Some("en-US"));
+        let german = LocaleFormat::for_locale(Some("de"));
+
+        assert_eq!(format_currency(-0.001, "USD", &english), "$0.00");
+        assert_eq!(format_currency(-0.4, "JPY", &english), "¥0");
+        assert_eq!(format_currency(-0.001, "EUR", &german), "0,00 €");
+        assert_eq!(format_currency(-0.006, "CHF", &english), "-0.01 CHF");
+    }
+}
@@ -0,0 +1,153 @@
+use super::{
+    repository::{
+        EmailTemplateRepository, FileEmailTemplateRepository, WritableEmailTemplateRepository,
+    },
+    sqlite::{PartialKind, SqliteEmailTemplateRepository},
+    template::TemplateError,
+};
+
+use crate::tracing::{info, warn};
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub templates: usize,
+    pub translations: usize,
+    pub partials: usize,
+    pub layouts: usize,
+    /// Templates and translations that failed to parse
+    pub skipped: usize,
+}
+
+/// Copies the JSON templates, translations, partials and layouts of a
+/// templates directory into the database, replacing the stored ones with the
+/// same names. Templates that fail to parse are skipped.
+pub async fn import_templates(
+    source: &FileEmailTemplateRepository,
+    target: &SqliteEmailTemplateRepository,
+) -> Result<ImportSummary, TemplateError> {
+    let mut summary = ImportSummary::default();
+
+    for partial in source.list_partials().await? {
+        target.save_partial(PartialKind::Partial, &partial).await?;
+        summary.partials += 1;
+    }
+
+    for layout in source.list_layouts().await? {
+        target.save_partial(PartialKind::Layout, &layout).await?;
+        summary.layouts += 1;
+    }
+
+    for (key, locales) in source.list_with_locales().await? {
+        let template = match source.find_by_id(&key).await {
+            Ok(template) => template,
+            Err(err) => {
+                warn!("Skipping email template {}: {}", key, err);
+                summary.skipped += 1;
+                continue;
+            }
+        };
+
+        target.save(&template).await?;
+        summary.templates += 1;
+
+        for locale in locales {
+            let translation = match source.find_localized(&key, &locale).await {
+                Ok(translation) => translation,
+                Err(err) => {
+                    warn!("Skipping email template {}.{}: {}", key, locale, err);
+                    summary.skipped += 1;
+                    continue;
+                }
+            };
+
+            target.save(&translation).await?;
+            summary.translations += 1;
+        }
+
+        info!("Imported email template {}", key);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::infra::sqlite;
+    use crate::templates::email::template::TemplateKey;
+
+    fn templates_directory() -> tempfile::TempDir {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path();
+
+        fs::create_dir_all(path.join("partials")).unwrap();
+        fs::create_dir_all(path.join("layouts")).unwrap();
+        fs::create_dir_all(path.join("organizations/acme")).unwrap();
+
+        fs::write(
+            path.join("welcome.json"),
+            r#"{"id": "welcome", "subject": "Welcome", "body": "{{> footer}}"}"#,
+        )
+        .unwrap();
+        fs::write(
+            path.join("welcome.pt-BR.json"),
+            r#"{"id": "welcome", "subject": "Bem-vindo", "body": "{{> footer}}"}"#,
+        )
+        .unwrap();
+        fs::write(path.join("welcome.es.json"), "{ not json").unwrap();
+        fs::write(path.join("broken.json"), "{ not json").unwrap();
+        fs::write(
+            path.join("organizations/acme/welcome.json"),
+            r#"{"id": "welcome", "subject": "Welcome to Acme", "body": "Hi"}"#,
+        )
+        .unwrap();
+        fs::write(path.join("partials/footer.hbs"), "<footer></footer>").unwrap();
+        fs::write(path.join("layouts/base.hbs"), "<main>{{{body}}}</main>").unwrap();
+
+        directory
+    }
+
+    #[tokio::test]
+    async fn imports_a_templates_directory() {
+        let directory = templates_directory();
+        let source = FileEmailTemplateRepository::new(directory.path().display().to_string());
+        let target = SqliteEmailTemplateRepository::new(sqlite::memory().await)
+            .await
+            .unwrap();
+
+        let summary = import_templates(&source, &target).await.unwrap();
+
+        assert_eq!(summary.templates, 2);
+        assert_eq!(summary.translations, 1);
+        assert_eq!(summary.partials, 1);
+        assert_eq!(summary.layouts, 1);
+        assert_eq!(summary.skipped, 2);
+
+        let shared = TemplateKey::shared("welcome");
+        let owned = TemplateKey::owned("acme", "welcome");
+
+        assert_eq!(
+            target.list_with_locales().await.unwrap(),
+            [
+                (shared.clone(), vec!["pt-BR".to_string()]),
+                (owned.clone(), Vec::new())
+            ]
+        );
+        assert_eq!(
+            target
+                .find_localized(&shared, "pt-BR")
+                .await
+                .unwrap()
+                .subject,
+            "Bem-vindo"
+        );
+        assert_eq!(
+            target.find_by_id(&owned).await.unwrap().subject,
+            "Welcome to Acme"
+        );
+        assert_eq!(target.list_partials().await.unwrap()[0].name, "footer");
+        assert_eq!(target.list_layouts().await.unwrap()[0].name, "base");
+    }
+}
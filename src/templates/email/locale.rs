@@ -0,0 +1,219 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::template::TemplateError;
+
+/// Language tags like `pt` or `pt-BR`. Locales become part of file names, so
+/// nothing else is allowed.
+pub static LOCALE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{2,8}){0,2}$").unwrap());
+
+/// Metadata key the engine sets to the locale a template is rendered in,
+/// read by the locale-aware helpers
+pub const LOCALE_KEY: &str = "_locale";
+
+pub fn validate_locale(locale: &str) -> Result<(), TemplateError> {
+    if LOCALE.is_match(locale) {
+        Ok(())
+    } else {
+        Err(TemplateError::InvalidLocale(locale.to_string()))
+    }
+}
+
+/// Normalizes the case of a language tag, so `PT-br` and `pt-BR` resolve to
+/// the same templates
+pub fn normalize_locale(locale: &str) -> String {
+    locale
+        .split('-')
+        .enumerate()
+        .map(|(index, part)| match (index, part.len()) {
+            (0, _) => part.to_lowercase(),
+            (_, 2) => part.to_uppercase(),
+            (_, 4) => {
+                let (first, rest) = part.split_at(1);
+                format!("{}{}", first.to_uppercase(), rest.to_lowercase())
+            }
+            _ => part.to_lowercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Locales to try for a template, most specific first: `pt-BR` falls back to
+/// `pt`, then to the default template
+pub fn fallback_chain(locale: &str) -> Vec<String> {
+    let parts: Vec<&str> = locale.split('-').collect();
+
+    (1..=parts.len())
+        .rev()
+        .map(|length| parts[..length].join("-"))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolPosition {
+    /// `$1,234.50`
+    Before,
+    /// `R$ 1.234,50`
+    BeforeSpaced,
+    /// `1.234,50 €`
+    After,
+}
+
+/// How numbers, amounts and dates are written in a locale
+#[derive(Debug, Clone, Copy)]
+pub struct LocaleFormat {
+    pub decimal_separator: char,
+    pub group_separator: char,
+    pub currency_symbol: SymbolPosition,
+    pub date_format: &'static str,
+    pub chrono_locale: chrono::Locale,
+}
+
+impl LocaleFormat {
+    /// Conventions for a language tag. Templates rendered without a locale
+    /// keep ISO dates and English numbers.
+    pub fn for_locale(locale: Option<&str>) -> Self {
+        let Some(locale) = locale else {
+            return Self::english("%Y-%m-%d", chrono::Locale::POSIX);
+        };
+
+        let locale = normalize_locale(locale);
+        let mut parts = locale.split('-');
+        let language = parts.next().unwrap_or_default();
+        let region = parts.find(|part| part.len() == 2);
+        let chrono_locale = chrono_locale(language, region);
+
+        match language {
+            "en" if region.is_none_or(|region| region == "US") => {
+                Self::english("%m/%d/%Y", chrono_locale)
+            }
+            "en" => Self::english("%d/%m/%Y", chrono_locale),
+            "pt" => Self::european(SymbolPosition::BeforeSpaced, "%d/%m/%Y", chrono_locale),
+            "de" => Self::european(SymbolPosition::After, "%d.%m.%Y", chrono_locale),
+            "fr" => Self {
+                group_separator: ' ',
+                ..Self::european(SymbolPosition::After, "%d/%m/%Y", chrono_locale)
+            },
+            "es" | "it" | "nl" => Self::european(SymbolPosition::After, "%d/%m/%Y", chrono_locale),
+            _ => Self::english("%Y-%m-%d", chrono_locale),
+        }
+    }
+
+    fn english(date_format: &'static str, chrono_locale: chrono::Locale) -> Self {
+        Self {
+            decimal_separator: '.',
+            group_separator: ',',
+            currency_symbol: SymbolPosition::Before,
+            date_format,
+            chrono_locale,
+        }
+    }
+
+    fn european(
+        currency_symbol: SymbolPosition,
+        date_format: &'static str,
+        chrono_locale: chrono::Locale,
+    ) -> Self {
+        Self {
+            decimal_separator: ',',
+            group_separator: '.',
+            currency_symbol,
+            date_format,
+            chrono_locale,
+        }
+    }
+
+    /// Formats a number with the locale's separators
+    pub fn format_number(&self, value: f64, decimals: usize) -> String {
+        let formatted = format!("{:.*}", decimals, value.abs());
+
+        let (integer, fraction) = match formatted.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+
+        let mut number = String::new();
+
+        // Amounts that round to zero are not negative
+        if value < 0.0 && formatted.chars().any(|digit| matches!(digit, '1'..='9')) {
+            number.push('-');
+        }
+
+        for (index, digit) in integer.chars().enumerate() {
+            if index > 0 && (integer.len() - index) % 3 == 0 {
+                number.push(self.group_separator);
+            }
+            number.push(digit);
+        }
+
+        if let Some(fraction) = fraction {
+            number.push(self.decimal_separator);
+            number.push_str(fraction);
+        }
+
+        number
+    }
+}
+
+/// Month and weekday names come from the full locale when chrono knows it,
+/// or from the most common region of the language
+fn chrono_locale(language: &str, region: Option<&str>) -> chrono::Locale {
+    let default_region = match language {
+        "en" => "US".to_string(),
+        "pt" => "BR".to_string(),
+        other => other.to_uppercase(),
+    };
+
+    let locale = region
+        .into_iter()
+        .chain([default_region.as_str()])
+        .find_map(|region| {
+            chrono::Locale::try_from(format!("{}_{}", language, region).as_str()).ok()
+        });
+
+    locale.unwrap_or(chrono::Locale::POSIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_locale_fixes_the_case_of_each_part() {
+        assert_eq!(normalize_locale("PT-br"), "pt-BR");
+        assert_eq!(normalize_locale("en"), "en");
+        assert_eq!(normalize_locale("ZH-hant-tw"), "zh-Hant-TW");
+        assert_eq!(normalize_locale("es-419"), "es-419");
+    }
+
+    #[test]
+    fn fallback_chain_goes_from_most_to_least_specific() {
+        assert_eq!(
+            fallback_chain("zh-Hant-TW"),
+            ["zh-Hant-TW", "zh-Hant", "zh"]
+        );
+        assert_eq!(fallback_chain("pt-BR"), ["pt-BR", "pt"]);
+        assert_eq!(fallback_chain("en"), ["en"]);
+    }
+
+    #[test]
+    fn format_number_uses_the_locale_separators() {
+        let english = LocaleFormat::for_locale(Some("en-US"));
+        let brazilian = LocaleFormat::for_locale(Some("pt-BR"));
+        let french = LocaleFormat::for_locale(Some("fr"));
+
+        assert_eq!(english.format_number(1234567.891, 2), "1,234,567.89");
+        assert_eq!(brazilian.format_number(1234567.891, 2), "1.234.567,89");
+        assert_eq!(french.format_number(1234.5, 1), "1 234,5");
+        assert_eq!(english.format_number(999.0, 0), "999");
+        assert_eq!(english.format_number(-1000.0, 0), "-1,000");
+    }
+
+    #[test]
+    fn format_number_never_shows_negative_zero() {
+        let english = LocaleFormat::for_locale(None);
+
+        assert_eq!(english.format_number(-0.001, 2), "0.00");
+    }
+}
@@ -1,3 +1,13 @@
+pub mod cache;
 pub mod engine;
+pub mod format;
+pub mod helpers;
+pub mod import;
+pub mod locale;
+pub mod processing;
 pub mod repository;
+pub mod sqlite;
 pub mod template;
+pub mod text;
+pub mod variables;
+pub mod versions;
@@ -0,0 +1,148 @@
+use css_inline::CSSInliner;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+use super::template::TemplateError;
+
+static BODY: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<body\b[^>]*>").unwrap());
+
+static PRESERVED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?is)<(pre|textarea)\b.*?</(pre|textarea)\s*>").unwrap());
+
+static COMMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<!--.*?-->").unwrap());
+
+static WHITESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+").unwrap());
+
+/// Steps applied to the rendered HTML of a template, each of which is
+/// turned on per template
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HtmlProcessing {
+    /// Move `<style>` rules into `style` attributes, since many clients
+    /// strip style blocks
+    pub inline_css: bool,
+    pub minify: bool,
+}
+
+/// Runs the post-render pipeline: preheader injection, CSS inlining, then
+/// minification
+pub fn process_html(
+    html: &str,
+    processing: &HtmlProcessing,
+    preheader: Option<&str>,
+) -> Result<String, TemplateError> {
+    let mut html = match preheader {
+        Some(preheader) if !preheader.trim().is_empty() => inject_preheader(html, preheader),
+        _ => html.to_string(),
+    };
+
+    if processing.inline_css {
+        html = inline_css(&html)?;
+    }
+
+    if processing.minify {
+        html = minify(&html);
+    }
+
+    Ok(html)
+}
+
+/// Adds the preheader as hidden text at the start of the body, where clients
+/// pick up the inbox preview
+fn inject_preheader(html: &str, preheader: &str) -> String {
+    let hidden = format!(
+        "<div style=\"display:none;max-height:0;overflow:hidden;mso-hide:all;\">{}</div>",
+        preheader
+    );
+
+    match BODY.find(html) {
+        Some(body) => format!("{}{}{}", &html[..body.end()], hidden, &html[body.end()..]),
+        None => format!("{}{}", hidden, html),
+    }
+}
+
+fn inline_css(html: &str) -> Result<String, TemplateError> {
+    // At-rules like media queries can't be inlined, so they stay in a style
+    // block for the clients that support them
+    let inliner = CSSInliner::options()
+        .load_remote_stylesheets(false)
+        .keep_at_rules(true)
+        .build();
+
+    inliner
+        .inline(html)
+        .map_err(|err| TemplateError::RenderError(format!("Failed to inline CSS: {}", err)))
+}
+
+/// Drops comments and collapses whitespace, leaving `<pre>` and `<textarea>`
+/// contents untouched. Outlook conditional comments are kept.
+fn minify(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    let mut last = 0;
+
+    for preserved in PRESERVED.find_iter(html) {
+        minified.push_str(&minify_fragment(&html[last..preserved.start()]));
+        minified.push_str(preserved.as_str());
+        last = preserved.end();
+    }
+
+    minified.push_str(&minify_fragment(&html[last..]));
+
+    minified.trim().to_string()
+}
+
+fn minify_fragment(html: &str) -> String {
+    let html = COMMENT.replace_all(html, |captures: &Captures| {
+        let comment = &captures[0];
+
+        if comment.starts_with("<!--[if") || comment.contains("<![endif]") {
+            comment.to_string()
+        } else {
+            String::new()
+        }
+    });
+
+    // Whitespace between tags may separate inline elements, so it collapses
+    // to a single space rather than being dropped
+    WHITESPACE.replace_all(&html, " ").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minify_collapses_whitespace_between_inline_elements() {
+        assert_eq!(
+            minify("<p>\n    <b>Hi</b>\n    <a href=\"#\">there</a>\n</p>\n"),
+            "<p> <b>Hi</b> <a href=\"#\">there</a> </p>"
+        );
+    }
+
+    #[test]
+    fn minify_drops_comments_but_keeps_outlook_conditionals() {
+        assert_eq!(
+            minify("<!-- note --><p>Hi</p><!--[if mso]><table><![endif]-->"),
+            "<p>Hi</p><!--[if mso]><table><![endif]-->"
+        );
+    }
+
+    #[test]
+    fn minify_keeps_preformatted_text() {
+        assert_eq!(
+            minify("<div>\n  <pre>  a\n  b</pre>\n  <textarea>x\n\ny</textarea>\n</div>"),
+            "<div> <pre>  a\n  b</pre> <textarea>x\n\ny</textarea> </div>"
+        );
+    }
+
+    #[test]
+    fn processing_is_off_by_default() {
+        let html = "<p>\n  Hi\n</p>";
+
+        assert_eq!(
+            process_html(html, &HtmlProcessing::default(), None).unwrap(),
+            html
+        );
+    }
+}
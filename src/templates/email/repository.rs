@@ -1,39 +1,910 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tokio::fs;
 
-use super::template::{EmailTemplate, TemplateError};
+use super::{
+    locale::{fallback_chain, normalize_locale, validate_locale},
+    template::{validate_template_id, EmailTemplate, TemplateError, TemplateKey},
+};
+
+use crate::tracing::{error, warn};
 
-use crate::tracing::error;
+/// Directory of the templates managed by organizations, one directory each
+const ORGANIZATIONS_DIRECTORY: &str = "organizations";
+
+/// Handlebars source shared between templates, either a partial or a layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplatePartial {
+    pub name: String,
+    pub source: String,
+}
 
 #[async_trait]
 pub trait EmailTemplateRepository: Send + Sync {
-    async fn find_by_id(&self, id: &str) -> Result<EmailTemplate, TemplateError>;
+    /// The default template, used when no translation matches
+    async fn find_by_id(&self, key: &TemplateKey) -> Result<EmailTemplate, TemplateError>;
+
+    /// The translation of a template to exactly this locale
+    async fn find_localized(
+        &self,
+        key: &TemplateKey,
+        locale: &str,
+    ) -> Result<EmailTemplate, TemplateError> {
+        let _ = locale;
+
+        Err(TemplateError::NotFound(key.to_string()))
+    }
+
+    /// Resolves a template for a locale, falling back from `pt-BR` to `pt`
+    /// and then to the default template
+    async fn resolve(
+        &self,
+        key: &TemplateKey,
+        locale: Option<&str>,
+    ) -> Result<EmailTemplate, TemplateError> {
+        for candidate in locale.map(fallback_chain).unwrap_or_default() {
+            match self.find_localized(key, &candidate).await {
+                Err(TemplateError::NotFound(_)) => continue,
+                result => return result,
+            }
+        }
+
+        self.find_by_id(key).await
+    }
+
+    /// Keys of every stored template with a default version, sorted
+    async fn list_keys(&self) -> Result<Vec<TemplateKey>, TemplateError>;
+
+    /// IDs of the templates the organization manages, sorted
+    async fn list_organization_ids(
+        &self,
+        organization_id: &str,
+    ) -> Result<Vec<String>, TemplateError> {
+        Ok(self
+            .list_keys()
+            .await?
+            .into_iter()
+            .filter(|key| key.organization_id.as_deref() == Some(organization_id))
+            .map(|key| key.id)
+            .collect())
+    }
+
+    /// Locales a template is translated to, sorted
+    async fn list_locales(&self, key: &TemplateKey) -> Result<Vec<String>, TemplateError> {
+        let _ = key;
+
+        Ok(Vec::new())
+    }
+
+    /// Keys of every template with a default version, each with the locales
+    /// it is translated to, sorted
+    async fn list_with_locales(&self) -> Result<Vec<(TemplateKey, Vec<String>)>, TemplateError> {
+        let mut templates = Vec::new();
+
+        for key in self.list_keys().await? {
+            let locales = self.list_locales(&key).await?;
+            templates.push((key, locales));
+        }
+
+        Ok(templates)
+    }
+
+    /// Partials every template can include
+    async fn list_partials(&self) -> Result<Vec<TemplatePartial>, TemplateError> {
+        Ok(Vec::new())
+    }
+
+    /// Layouts templates can wrap their body in
+    async fn list_layouts(&self) -> Result<Vec<TemplatePartial>, TemplateError> {
+        Ok(Vec::new())
+    }
+
+    /// Changes whenever a template, partial or layout is saved or deleted,
+    /// for stores shared between instances. `None` for stores that are
+    /// watched instead.
+    async fn revision(&self) -> Result<Option<String>, TemplateError> {
+        Ok(None)
+    }
+
+    /// Where the template is stored, for error messages
+    fn location(&self, key: &TemplateKey, locale: Option<&str>) -> String {
+        match locale {
+            Some(locale) => format!("{}.{}", key, locale),
+            None => key.to_string(),
+        }
+    }
 }
 
+/// A repository templates can be created, changed and deleted in
+#[async_trait]
+pub trait WritableEmailTemplateRepository: EmailTemplateRepository {
+    /// Stores the template, or its translation when it has a locale, under
+    /// its key, replacing any stored version
+    async fn save(&self, template: &EmailTemplate) -> Result<(), TemplateError>;
+
+    /// Deletes the template with its translations, or only the translation
+    /// to the locale
+    async fn delete(&self, key: &TemplateKey, locale: Option<&str>) -> Result<(), TemplateError>;
+}
+
+/// Stores shared templates in the templates directory, and the templates of
+/// an organization in its `organizations/{organization_id}` directory
 pub struct FileEmailTemplateRepository {
     templates_path: String,
 }
 
+/// A template file, named `{id}.json` or `{id}.{locale}.json`
+struct TemplateFile {
+    key: TemplateKey,
+    /// The normalized locale of a translation
+    locale: Option<String>,
+    /// The locale as spelled in the file name
+    file_locale: Option<String>,
+}
+
 impl FileEmailTemplateRepository {
     pub fn new(templates_path: String) -> Self {
         Self { templates_path }
     }
-}
 
-#[async_trait]
-impl EmailTemplateRepository for FileEmailTemplateRepository {
-    async fn find_by_id(&self, id: &str) -> Result<EmailTemplate, TemplateError> {
-        let path = format!("{}/{}.json", self.templates_path, id);
+    /// The directory of an organization's templates, or of the shared ones
+    fn directory(&self, organization_id: Option<&str>) -> Result<String, TemplateError> {
+        match organization_id {
+            Some(organization_id) => {
+                // Organization IDs become directory names
+                validate_template_id(organization_id)?;
+
+                Ok(format!(
+                    "{}/{}/{}",
+                    self.templates_path, ORGANIZATIONS_DIRECTORY, organization_id
+                ))
+            }
+            None => Ok(self.templates_path.clone()),
+        }
+    }
+
+    /// The file a template is stored in, which may not exist yet
+    fn template_path(
+        &self,
+        key: &TemplateKey,
+        locale: Option<&str>,
+    ) -> Result<PathBuf, TemplateError> {
+        Ok(Path::new(&self.directory(key.organization_id.as_deref())?)
+            .join(template_file_name(&key.id, locale)?))
+    }
+
+    async fn read_template(
+        &self,
+        key: &TemplateKey,
+        locale: Option<&str>,
+    ) -> Result<EmailTemplate, TemplateError> {
+        let directory = self.directory(key.organization_id.as_deref())?;
+        let locale = locale.map(normalize_locale);
+
+        let path = match resolve_template_path(&directory, &key.id, locale.as_deref()).await {
+            Err(TemplateError::NotFound(_)) if locale.is_some() => {
+                let file_locale = self.file_locale(key, locale.as_deref()).await?;
+
+                resolve_template_path(&directory, &key.id, file_locale.as_deref()).await
+            }
+            result => result,
+        }
+        // Reported by key, since IDs are only unique within an organization
+        .map_err(|err| match err {
+            TemplateError::NotFound(_) => TemplateError::NotFound(key.to_string()),
+            err => err,
+        })?;
 
         let content = fs::read_to_string(&path).await.map_err(|err| {
             error!("Failed to read email template file: {:?}", err);
 
-            TemplateError::NotFound(id.to_string())
+            io_error(err, &key.to_string())
         })?;
 
-        let template: EmailTemplate = serde_json::from_str(&content)
-            .map_err(|e| TemplateError::RenderError(e.to_string()))?;
+        let mut template: EmailTemplate = serde_json::from_str(&content)
+            .map_err(|err| TemplateError::invalid_json(&key.id, &path, err))?;
+
+        // Templates are looked up by file name and directory
+        template.id = key.id.clone();
+        template.organization_id = key.organization_id.clone();
+        template.locale = locale;
 
         Ok(template)
     }
+
+    /// How the locale is spelled in the name of a template file, which may
+    /// differ in case from the normalized locale. A new file uses the
+    /// normalized locale.
+    async fn file_locale(
+        &self,
+        key: &TemplateKey,
+        locale: Option<&str>,
+    ) -> Result<Option<String>, TemplateError> {
+        let Some(locale) = locale.map(normalize_locale) else {
+            return Ok(None);
+        };
+
+        let file_locale = self
+            .list_files()
+            .await?
+            .into_iter()
+            .find(|file| file.key == *key && file.locale.as_deref() == Some(locale.as_str()))
+            .and_then(|file| file.file_locale);
+
+        Ok(Some(file_locale.unwrap_or(locale)))
+    }
+
+    /// Every template file, shared or of an organization, sorted by key and
+    /// locale
+    async fn list_files(&self) -> Result<Vec<TemplateFile>, TemplateError> {
+        let mut files = Vec::new();
+
+        list_directory(Path::new(&self.templates_path), None, &mut files).await?;
+
+        let organizations = Path::new(&self.templates_path).join(ORGANIZATIONS_DIRECTORY);
+
+        if fs::try_exists(&organizations).await.unwrap_or(false) {
+            let mut entries = fs::read_dir(&organizations).await.map_err(|err| {
+                error!("Failed to read {:?}: {:?}", organizations, err);
+
+                io_error(err, &organizations.display().to_string())
+            })?;
+
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|err| io_error(err, &organizations.display().to_string()))?
+            {
+                let path = entry.path();
+
+                let Some(organization_id) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+
+                if !path.is_dir() || validate_template_id(organization_id).is_err() {
+                    continue;
+                }
+
+                list_directory(&path, Some(organization_id), &mut files).await?;
+            }
+        }
+
+        files.sort_by(|a, b| (&a.key, &a.locale).cmp(&(&b.key, &b.locale)));
+
+        Ok(files)
+    }
+}
+
+#[async_trait]
+impl EmailTemplateRepository for FileEmailTemplateRepository {
+    async fn find_by_id(&self, key: &TemplateKey) -> Result<EmailTemplate, TemplateError> {
+        self.read_template(key, None).await
+    }
+
+    async fn find_localized(
+        &self,
+        key: &TemplateKey,
+        locale: &str,
+    ) -> Result<EmailTemplate, TemplateError> {
+        self.read_template(key, Some(locale)).await
+    }
+
+    fn location(&self, key: &TemplateKey, locale: Option<&str>) -> String {
+        let directory = match &key.organization_id {
+            Some(organization_id) => format!(
+                "{}/{}/{}",
+                self.templates_path, ORGANIZATIONS_DIRECTORY, organization_id
+            ),
+            None => self.templates_path.clone(),
+        };
+
+        match locale {
+            Some(locale) => format!("{}/{}.{}.json", directory, key.id, locale),
+            None => format!("{}/{}.json", directory, key.id),
+        }
+    }
+
+    async fn list_keys(&self) -> Result<Vec<TemplateKey>, TemplateError> {
+        let files = self.list_files().await?;
+
+        Ok(files
+            .into_iter()
+            .filter(|file| file.locale.is_none())
+            .map(|file| file.key)
+            .collect())
+    }
+
+    async fn list_locales(&self, key: &TemplateKey) -> Result<Vec<String>, TemplateError> {
+        let files = self.list_files().await?;
+
+        Ok(files
+            .into_iter()
+            .filter(|file| file.key == *key)
+            .filter_map(|file| file.locale)
+            .collect())
+    }
+
+    async fn list_with_locales(&self) -> Result<Vec<(TemplateKey, Vec<String>)>, TemplateError> {
+        let files = self.list_files().await?;
+        let mut templates: Vec<(TemplateKey, Vec<String>)> = Vec::new();
+
+        // Sorted by key, with the default version before the translations
+        for file in files {
+            match (file.locale, templates.last_mut()) {
+                (None, _) => templates.push((file.key, Vec::new())),
+                (Some(locale), Some((key, locales))) if *key == file.key => locales.push(locale),
+                // A translation without a default version
+                (Some(_), _) => {}
+            }
+        }
+
+        Ok(templates)
+    }
+
+    async fn list_partials(&self) -> Result<Vec<TemplatePartial>, TemplateError> {
+        read_partials(&Path::new(&self.templates_path).join("partials")).await
+    }
+
+    async fn list_layouts(&self) -> Result<Vec<TemplatePartial>, TemplateError> {
+        read_partials(&Path::new(&self.templates_path).join("layouts")).await
+    }
+}
+
+#[async_trait]
+impl WritableEmailTemplateRepository for FileEmailTemplateRepository {
+    async fn save(&self, template: &EmailTemplate) -> Result<(), TemplateError> {
+        let key = template.key();
+
+        // Replaces the file of the translation however its locale is spelled
+        let file_locale = self.file_locale(&key, template.locale.as_deref()).await?;
+        let path = self.template_path(&key, file_locale.as_deref())?;
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).await.map_err(|err| {
+                error!("Failed to create email templates directory: {:?}", err);
+
+                TemplateError::StoreError(err.to_string())
+            })?;
+        }
+
+        let content = serde_json::to_string_pretty(template)
+            .map_err(|err| TemplateError::StoreError(err.to_string()))?;
+
+        // Written aside and renamed, so the watcher and readers never see a
+        // partially written file
+        let temporary = path.with_extension("json.tmp");
+
+        fs::write(&temporary, content).await.map_err(|err| {
+            error!("Failed to write email template file: {:?}", err);
+
+            TemplateError::StoreError(err.to_string())
+        })?;
+
+        fs::rename(&temporary, &path).await.map_err(|err| {
+            error!("Failed to replace email template file: {:?}", err);
+
+            TemplateError::StoreError(err.to_string())
+        })
+    }
+
+    async fn delete(&self, key: &TemplateKey, locale: Option<&str>) -> Result<(), TemplateError> {
+        let locales = match locale {
+            Some(locale) => vec![self.file_locale(key, Some(locale)).await?],
+            None => {
+                let mut locales: Vec<Option<String>> = self
+                    .list_files()
+                    .await?
+                    .into_iter()
+                    .filter(|file| file.key == *key && file.locale.is_some())
+                    .map(|file| file.file_locale)
+                    .collect();
+                locales.push(None);
+                locales
+            }
+        };
+
+        for locale in locales {
+            let path = self.template_path(key, locale.as_deref())?;
+
+            match fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(TemplateError::NotFound(key.to_string()));
+                }
+                Err(err) => {
+                    error!("Failed to delete email template file: {:?}", err);
+
+                    return Err(TemplateError::StoreError(err.to_string()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds the `{id}.json` and `{id}.{locale}.json` files of a directory
+async fn list_directory(
+    directory: &Path,
+    organization_id: Option<&str>,
+    files: &mut Vec<TemplateFile>,
+) -> Result<(), TemplateError> {
+    let mut entries = fs::read_dir(directory).await.map_err(|err| {
+        error!("Failed to read email templates directory: {:?}", err);
+
+        io_error(err, &directory.display().to_string())
+    })?;
+
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|err| io_error(err, &directory.display().to_string()))?
+    {
+        let path = entry.path();
+
+        if !path.is_file() || path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let (id, locale) = match stem.split_once('.') {
+            Some((id, locale)) => (id, Some(locale)),
+            None => (stem, None),
+        };
+
+        if validate_template_id(id).is_err()
+            || locale.is_some_and(|locale| validate_locale(locale).is_err())
+        {
+            warn!(
+                "Skipping email template with an invalid file name: {:?}",
+                path
+            );
+            continue;
+        }
+
+        files.push(TemplateFile {
+            key: TemplateKey {
+                organization_id: organization_id.map(str::to_string),
+                id: id.to_string(),
+            },
+            locale: locale.map(normalize_locale),
+            file_locale: locale.map(str::to_string),
+        });
+    }
+
+    Ok(())
+}
+
+/// Reads every `.hbs` file of a directory, named after its file stem. A
+/// missing directory has no partials.
+async fn read_partials(directory: &Path) -> Result<Vec<TemplatePartial>, TemplateError> {
+    if !fs::try_exists(directory).await.unwrap_or(false) {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = fs::read_dir(directory).await.map_err(|err| {
+        error!("Failed to read {:?}: {:?}", directory, err);
+
+        io_error(err, &directory.display().to_string())
+    })?;
+
+    let mut partials = Vec::new();
+
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|err| io_error(err, &directory.display().to_string()))?
+    {
+        let path = entry.path();
+
+        if !path.is_file() || path.extension().is_none_or(|extension| extension != "hbs") {
+            continue;
+        }
+
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        if validate_template_id(name).is_err() {
+            warn!("Skipping partial with an invalid file name: {:?}", path);
+            continue;
+        }
+
+        let source = fs::read_to_string(&path).await.map_err(|err| {
+            error!("Failed to read partial file: {:?}", err);
+
+            io_error(err, name)
+        })?;
+
+        partials.push(TemplatePartial {
+            name: name.to_string(),
+            source,
+        });
+    }
+
+    partials.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(partials)
+}
+
+/// Resolves the file of a template, or of its translation to a locale,
+/// making sure it stays under the templates directory even through symlinks
+pub(crate) async fn resolve_template_path(
+    templates_path: &str,
+    id: &str,
+    locale: Option<&str>,
+) -> Result<PathBuf, TemplateError> {
+    let file_name = template_file_name(id, locale)?;
+
+    let root = fs::canonicalize(templates_path).await.map_err(|err| {
+        error!(
+            "Failed to resolve templates directory {}: {:?}",
+            templates_path, err
+        );
+
+        io_error(err, id)
+    })?;
+
+    let path = fs::canonicalize(root.join(file_name))
+        .await
+        .map_err(|err| io_error(err, id))?;
+
+    if !path.starts_with(&root) {
+        error!("Template {} resolves outside of {}", id, templates_path);
+
+        return Err(TemplateError::InvalidId(id.to_string()));
+    }
+
+    Ok(path)
+}
+
+/// Missing files are not found, while other I/O errors, like denied
+/// permissions, are storage failures that may go away on a retry
+pub(crate) fn io_error(err: io::Error, name: &str) -> TemplateError {
+    match err.kind() {
+        io::ErrorKind::NotFound => TemplateError::NotFound(name.to_string()),
+        _ => TemplateError::StoreError(err.to_string()),
+    }
+}
+
+/// `{id}.json`, or `{id}.{locale}.json` for a translation
+fn template_file_name(id: &str, locale: Option<&str>) -> Result<String, TemplateError> {
+    validate_template_id(id)?;
+
+    match locale {
+        Some(locale) => {
+            validate_locale(locale)?;
+            Ok(format!("{}.{}.json", id, locale))
+        }
+        None => Ok(format!("{}.json", id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates_directory() -> tempfile::TempDir {
+        let directory = tempfile::tempdir().unwrap();
+
+        std::fs::write(directory.path().join("welcome.json"), "{}").unwrap();
+        std::fs::write(directory.path().join("welcome.pt-BR.json"), "{}").unwrap();
+
+        directory
+    }
+
+    async fn resolve(
+        directory: &tempfile::TempDir,
+        id: &str,
+        locale: Option<&str>,
+    ) -> Result<PathBuf, TemplateError> {
+        resolve_template_path(directory.path().to_str().unwrap(), id, locale).await
+    }
+
+    #[tokio::test]
+    async fn resolves_templates_and_translations_under_the_directory() {
+        let directory = templates_directory();
+        let root = directory.path().canonicalize().unwrap();
+
+        assert_eq!(
+            resolve(&directory, "welcome", None).await.unwrap(),
+            root.join("welcome.json")
+        );
+        assert_eq!(
+            resolve(&directory, "welcome", Some("pt-BR")).await.unwrap(),
+            root.join("welcome.pt-BR.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_templates_are_not_found() {
+        let directory = templates_directory();
+
+        assert!(matches!(
+            resolve(&directory, "missing", None).await,
+            Err(TemplateError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_parent_directory_ids() {
+        let directory = templates_directory();
+
+        for id in ["..", "../welcome", "../../etc/passwd", "nested/../welcome"] {
+            assert!(
+                matches!(
+                    resolve(&directory, id, None).await,
+                    Err(TemplateError::InvalidId(_))
+                ),
+                "{}",
+                id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_absolute_paths() {
+        let directory = templates_directory();
+        let absolute = directory.path().join("welcome");
+
+        assert!(matches!(
+            resolve(&directory, absolute.to_str().unwrap(), None).await,
+            Err(TemplateError::InvalidId(_))
+        ));
+        assert!(matches!(
+            resolve(&directory, "/etc/passwd", None).await,
+            Err(TemplateError::InvalidId(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_ids_and_locales() {
+        let directory = templates_directory();
+
+        for id in ["", "-welcome", "welcome.json", "wel come", &"a".repeat(129)] {
+            assert!(
+                matches!(
+                    resolve(&directory, id, None).await,
+                    Err(TemplateError::InvalidId(_))
+                ),
+                "{}",
+                id
+            );
+        }
+
+        assert!(matches!(
+            resolve(&directory, "welcome", Some("../pt")).await,
+            Err(TemplateError::InvalidLocale(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn rejects_symlinks_escaping_the_directory() {
+        let directory = templates_directory();
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("secret.json");
+
+        std::fs::write(&secret, "{}").unwrap();
+        std::os::unix::fs::symlink(&secret, directory.path().join("escape.json")).unwrap();
+
+        assert!(matches!(
+            resolve(&directory, "escape", None).await,
+            Err(TemplateError::InvalidId(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn follows_symlinks_within_the_directory() {
+        let directory = templates_directory();
+
+        std::os::unix::fs::symlink(
+            directory.path().join("welcome.json"),
+            directory.path().join("alias.json"),
+        )
+        .unwrap();
+
+        assert!(resolve(&directory, "alias", None).await.is_ok());
+    }
+
+    #[test]
+    fn storage_errors_are_not_reported_as_missing() {
+        assert!(matches!(
+            io_error(io::Error::from(io::ErrorKind::NotFound), "welcome"),
+            TemplateError::NotFound(_)
+        ));
+        assert!(matches!(
+            io_error(io::Error::from(io::ErrorKind::PermissionDenied), "welcome"),
+            TemplateError::StoreError(_)
+        ));
+    }
+
+    fn file_repository(directory: &tempfile::TempDir) -> FileEmailTemplateRepository {
+        FileEmailTemplateRepository::new(directory.path().display().to_string())
+    }
+
+    fn template(key: &TemplateKey, locale: Option<&str>, subject: &str) -> EmailTemplate {
+        let mut template: EmailTemplate = serde_json::from_value(serde_json::json!({
+            "id": key.id,
+            "subject": subject,
+            "body": "<p>Hello</p>",
+        }))
+        .unwrap();
+
+        template.organization_id = key.organization_id.clone();
+        template.locale = locale.map(str::to_string);
+        template
+    }
+
+    #[tokio::test]
+    async fn saves_organization_templates_in_their_directory() {
+        let directory = tempfile::tempdir().unwrap();
+        let repository = file_repository(&directory);
+        let key = TemplateKey::owned("acme", "welcome");
+
+        repository
+            .save(&template(&key, None, "Welcome"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&key, Some("pt-br"), "Bem-vindo"))
+            .await
+            .unwrap();
+
+        let organization = directory.path().join("organizations/acme");
+        assert!(organization.join("welcome.json").is_file());
+        assert!(organization.join("welcome.pt-BR.json").is_file());
+        assert!(!directory.path().join("welcome.json").exists());
+
+        assert_eq!(
+            repository
+                .find_localized(&key, "pt-BR")
+                .await
+                .unwrap()
+                .subject,
+            "Bem-vindo"
+        );
+    }
+
+    #[tokio::test]
+    async fn saving_a_translation_replaces_its_file_however_it_is_spelled() {
+        let directory = templates_directory();
+        let repository = file_repository(&directory);
+        let key = TemplateKey::shared("welcome");
+
+        repository
+            .save(&template(&key, Some("PT-br"), "Bem-vindo"))
+            .await
+            .unwrap();
+
+        assert_eq!(repository.list_locales(&key).await.unwrap(), ["pt-BR"]);
+        assert_eq!(
+            repository
+                .find_localized(&key, "pt-BR")
+                .await
+                .unwrap()
+                .subject,
+            "Bem-vindo"
+        );
+    }
+
+    #[tokio::test]
+    async fn saves_are_kept_inside_the_directory() {
+        let directory = tempfile::tempdir().unwrap();
+        let repository = file_repository(&directory);
+
+        assert!(matches!(
+            repository
+                .save(&template(
+                    &TemplateKey::shared("../welcome"),
+                    None,
+                    "Welcome"
+                ))
+                .await,
+            Err(TemplateError::InvalidId(_))
+        ));
+        assert!(matches!(
+            repository
+                .save(&template(
+                    &TemplateKey::owned("..", "welcome"),
+                    None,
+                    "Welcome"
+                ))
+                .await,
+            Err(TemplateError::InvalidId(_))
+        ));
+        assert!(matches!(
+            repository
+                .save(&template(
+                    &TemplateKey::shared("welcome"),
+                    Some("../../pt"),
+                    "Welcome"
+                ))
+                .await,
+            Err(TemplateError::InvalidLocale(_))
+        ));
+
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn saves_replace_symlinks_instead_of_writing_through_them() {
+        let directory = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("secret.json");
+
+        std::fs::write(&secret, "{}").unwrap();
+        std::os::unix::fs::symlink(&secret, directory.path().join("escape.json")).unwrap();
+
+        file_repository(&directory)
+            .save(&template(&TemplateKey::shared("escape"), None, "Escape"))
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&secret).unwrap(), "{}");
+        assert!(!directory
+            .path()
+            .join("escape.json")
+            .symlink_metadata()
+            .unwrap()
+            .is_symlink());
+    }
+
+    #[tokio::test]
+    async fn deleting_a_translation_keeps_the_default_template() {
+        let directory = templates_directory();
+        let repository = file_repository(&directory);
+        let key = TemplateKey::shared("welcome");
+
+        repository.delete(&key, Some("pt-br")).await.unwrap();
+
+        assert!(directory.path().join("welcome.json").is_file());
+        assert!(!directory.path().join("welcome.pt-BR.json").exists());
+        assert!(matches!(
+            repository.delete(&key, Some("pt-BR")).await,
+            Err(TemplateError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn deleting_the_default_template_deletes_its_translations() {
+        let directory = templates_directory();
+        let repository = file_repository(&directory);
+        let key = TemplateKey::shared("welcome");
+
+        std::fs::write(directory.path().join("welcome.es.json"), "{}").unwrap();
+
+        repository.delete(&key, None).await.unwrap();
+
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+        assert!(repository.list_with_locales().await.unwrap().is_empty());
+        assert!(matches!(
+            repository.delete(&key, None).await,
+            Err(TemplateError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn deleting_an_organization_template_keeps_the_shared_one() {
+        let directory = templates_directory();
+        let repository = file_repository(&directory);
+        let key = TemplateKey::owned("acme", "welcome");
+
+        repository
+            .save(&template(&key, None, "Welcome to Acme"))
+            .await
+            .unwrap();
+        repository.delete(&key, None).await.unwrap();
+
+        assert!(directory.path().join("welcome.json").is_file());
+        assert!(directory.path().join("welcome.pt-BR.json").is_file());
+    }
 }
@@ -0,0 +1,521 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::SqlitePool;
+
+use super::{
+    locale::{normalize_locale, validate_locale},
+    repository::{EmailTemplateRepository, TemplatePartial, WritableEmailTemplateRepository},
+    template::{validate_template_id, EmailTemplate, TemplateError, TemplateKey},
+};
+
+// Shared templates are stored with an empty organization ID, and default
+// templates with an empty locale, since NULLs never conflict in the primary
+// key and saving them again would add rows instead of replacing them.
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS email_templates (
+    organization_id TEXT NOT NULL DEFAULT '',
+    template_id TEXT NOT NULL,
+    locale TEXT NOT NULL DEFAULT '',
+    content TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (organization_id, template_id, locale)
+);
+
+CREATE TABLE IF NOT EXISTS email_template_partials (
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    source TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (kind, name)
+);
+"#;
+
+const DEFAULT_LOCALE: &str = "";
+
+const SHARED: &str = "";
+
+/// Whether a stored partial is included with `{{> name}}` or wraps bodies as
+/// a layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialKind {
+    Partial,
+    Layout,
+}
+
+impl PartialKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            PartialKind::Partial => "partial",
+            PartialKind::Layout => "layout",
+        }
+    }
+}
+
+/// Stores templates in the database, keyed by the organization managing
+/// them, so instances don't need to ship the same templates directory
+pub struct SqliteEmailTemplateRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteEmailTemplateRepository {
+    pub async fn new(pool: SqlitePool) -> Result<Self, TemplateError> {
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .map_err(store_error)?;
+
+        Ok(Self { pool })
+    }
+
+    /// Stores a partial or a layout, replacing any with the same name
+    pub async fn save_partial(
+        &self,
+        kind: PartialKind,
+        partial: &TemplatePartial,
+    ) -> Result<(), TemplateError> {
+        validate_template_id(&partial.name)?;
+
+        sqlx::query(
+            "INSERT INTO email_template_partials (kind, name, source, updated_at)
+             VALUES (?, ?, ?, ?)
+             ON CONFLICT (kind, name) DO UPDATE
+             SET source = excluded.source, updated_at = excluded.updated_at",
+        )
+        .bind(kind.as_str())
+        .bind(&partial.name)
+        .bind(&partial.source)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(())
+    }
+
+    async fn read_template(
+        &self,
+        key: &TemplateKey,
+        locale: Option<&str>,
+    ) -> Result<EmailTemplate, TemplateError> {
+        let locale = locale.map(normalize_locale);
+
+        let content: String = sqlx::query_scalar(
+            "SELECT content FROM email_templates
+             WHERE organization_id = ? AND template_id = ? AND locale = ?",
+        )
+        .bind(owner(key))
+        .bind(&key.id)
+        .bind(locale.as_deref().unwrap_or(DEFAULT_LOCALE))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(store_error)?
+        .ok_or_else(|| TemplateError::NotFound(key.to_string()))?;
+
+        let mut template: EmailTemplate =
+            serde_json::from_str(&content).map_err(|err| TemplateError::InvalidTemplate {
+                id: key.id.clone(),
+                location: self.location(key, locale.as_deref()),
+                message: err.to_string(),
+            })?;
+
+        // The key columns win over the stored content
+        template.id = key.id.clone();
+        template.organization_id = key.organization_id.clone();
+        template.locale = locale;
+
+        Ok(template)
+    }
+
+    async fn read_partials(
+        &self,
+        kind: PartialKind,
+    ) -> Result<Vec<TemplatePartial>, TemplateError> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT name, source FROM email_template_partials WHERE kind = ? ORDER BY name",
+        )
+        .bind(kind.as_str())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(name, source)| TemplatePartial { name, source })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl EmailTemplateRepository for SqliteEmailTemplateRepository {
+    async fn find_by_id(&self, key: &TemplateKey) -> Result<EmailTemplate, TemplateError> {
+        self.read_template(key, None).await
+    }
+
+    async fn find_localized(
+        &self,
+        key: &TemplateKey,
+        locale: &str,
+    ) -> Result<EmailTemplate, TemplateError> {
+        self.read_template(key, Some(locale)).await
+    }
+
+    async fn list_keys(&self) -> Result<Vec<TemplateKey>, TemplateError> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT organization_id, template_id FROM email_templates
+             WHERE locale = ?
+             ORDER BY organization_id, template_id",
+        )
+        .bind(DEFAULT_LOCALE)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(organization_id, id)| into_key(organization_id, id))
+            .collect())
+    }
+
+    async fn list_organization_ids(
+        &self,
+        organization_id: &str,
+    ) -> Result<Vec<String>, TemplateError> {
+        sqlx::query_scalar(
+            "SELECT template_id FROM email_templates
+             WHERE organization_id = ? AND locale = ?
+             ORDER BY template_id",
+        )
+        .bind(organization_id)
+        .bind(DEFAULT_LOCALE)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(store_error)
+    }
+
+    async fn list_locales(&self, key: &TemplateKey) -> Result<Vec<String>, TemplateError> {
+        sqlx::query_scalar(
+            "SELECT locale FROM email_templates
+             WHERE organization_id = ? AND template_id = ? AND locale <> ?
+             ORDER BY locale",
+        )
+        .bind(owner(key))
+        .bind(&key.id)
+        .bind(DEFAULT_LOCALE)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(store_error)
+    }
+
+    async fn list_with_locales(&self) -> Result<Vec<(TemplateKey, Vec<String>)>, TemplateError> {
+        let rows: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT organization_id, template_id, locale FROM email_templates
+             ORDER BY organization_id, template_id, locale",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        let mut templates: Vec<(TemplateKey, Vec<String>)> = Vec::new();
+
+        // The default version sorts first, since its locale is empty
+        for (organization_id, id, locale) in rows {
+            let key = into_key(organization_id, id);
+
+            match templates.last_mut() {
+                _ if locale == DEFAULT_LOCALE => templates.push((key, Vec::new())),
+                Some((last_key, locales)) if *last_key == key => locales.push(locale),
+                // A translation without a default version
+                _ => {}
+            }
+        }
+
+        Ok(templates)
+    }
+
+    async fn list_partials(&self) -> Result<Vec<TemplatePartial>, TemplateError> {
+        self.read_partials(PartialKind::Partial).await
+    }
+
+    async fn list_layouts(&self) -> Result<Vec<TemplatePartial>, TemplateError> {
+        self.read_partials(PartialKind::Layout).await
+    }
+
+    // Deletes don't change the latest update, but they do change the count
+    async fn revision(&self) -> Result<Option<String>, TemplateError> {
+        let (count, updated_at): (i64, String) = sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(MAX(updated_at), '') FROM (
+                 SELECT updated_at FROM email_templates
+                 UNION ALL
+                 SELECT updated_at FROM email_template_partials
+             )",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(Some(format!("{}:{}", count, updated_at)))
+    }
+}
+
+#[async_trait]
+impl WritableEmailTemplateRepository for SqliteEmailTemplateRepository {
+    async fn save(&self, template: &EmailTemplate) -> Result<(), TemplateError> {
+        validate_template_id(&template.id)?;
+
+        if let Some(locale) = &template.locale {
+            validate_locale(locale)?;
+        }
+
+        let locale = template.locale.as_deref().map(normalize_locale);
+
+        let content = serde_json::to_string(template)
+            .map_err(|err| TemplateError::StoreError(err.to_string()))?;
+
+        // The organization is part of the key, so a template never moves
+        // to another organization
+        sqlx::query(
+            "INSERT INTO email_templates (organization_id, template_id, locale, content, updated_at)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT (organization_id, template_id, locale) DO UPDATE
+             SET content = excluded.content,
+                 updated_at = excluded.updated_at",
+        )
+        .bind(owner(&template.key()))
+        .bind(&template.id)
+        .bind(locale.as_deref().unwrap_or(DEFAULT_LOCALE))
+        .bind(content)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &TemplateKey, locale: Option<&str>) -> Result<(), TemplateError> {
+        let result = match locale {
+            Some(locale) => {
+                sqlx::query(
+                    "DELETE FROM email_templates
+                     WHERE organization_id = ? AND template_id = ? AND locale = ?",
+                )
+                .bind(owner(key))
+                .bind(&key.id)
+                .bind(normalize_locale(locale))
+                .execute(&self.pool)
+                .await
+            }
+            None => {
+                sqlx::query(
+                    "DELETE FROM email_templates WHERE organization_id = ? AND template_id = ?",
+                )
+                .bind(owner(key))
+                .bind(&key.id)
+                .execute(&self.pool)
+                .await
+            }
+        }
+        .map_err(store_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(TemplateError::NotFound(key.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// The organization column of a key
+fn owner(key: &TemplateKey) -> &str {
+    key.organization_id.as_deref().unwrap_or(SHARED)
+}
+
+fn into_key(organization_id: String, id: String) -> TemplateKey {
+    TemplateKey {
+        organization_id: Some(organization_id).filter(|organization_id| organization_id != SHARED),
+        id,
+    }
+}
+
+fn store_error(err: sqlx::Error) -> TemplateError {
+    TemplateError::StoreError(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::infra::sqlite;
+
+    async fn repository() -> SqliteEmailTemplateRepository {
+        SqliteEmailTemplateRepository::new(sqlite::memory().await)
+            .await
+            .unwrap()
+    }
+
+    fn template(key: &TemplateKey, locale: Option<&str>, subject: &str) -> EmailTemplate {
+        let mut template: EmailTemplate = serde_json::from_value(json!({
+            "id": key.id,
+            "subject": subject,
+            "body": "<p>Hello {{name}}</p>",
+        }))
+        .unwrap();
+
+        template.organization_id = key.organization_id.clone();
+        template.locale = locale.map(str::to_string);
+        template
+    }
+
+    #[tokio::test]
+    async fn saved_templates_round_trip() {
+        let repository = repository().await;
+        let key = TemplateKey::owned("acme", "welcome");
+
+        repository
+            .save(&template(&key, None, "Welcome"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&key, Some("pt-br"), "Bem-vindo"))
+            .await
+            .unwrap();
+
+        let default = repository.find_by_id(&key).await.unwrap();
+        assert_eq!(default.subject, "Welcome");
+        assert_eq!(default.organization_id.as_deref(), Some("acme"));
+        assert_eq!(default.locale, None);
+
+        let translation = repository.find_localized(&key, "pt-BR").await.unwrap();
+        assert_eq!(translation.subject, "Bem-vindo");
+        assert_eq!(translation.locale.as_deref(), Some("pt-BR"));
+
+        assert!(matches!(
+            repository.find_by_id(&TemplateKey::shared("welcome")).await,
+            Err(TemplateError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn saving_a_template_again_replaces_it() {
+        let repository = repository().await;
+        let key = TemplateKey::shared("welcome");
+
+        repository
+            .save(&template(&key, None, "Welcome"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&key, None, "Welcome back"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            repository.find_by_id(&key).await.unwrap().subject,
+            "Welcome back"
+        );
+        assert_eq!(repository.list_keys().await.unwrap(), [key]);
+    }
+
+    #[tokio::test]
+    async fn lists_templates_with_their_locales() {
+        let repository = repository().await;
+        let shared = TemplateKey::shared("welcome");
+        let owned = TemplateKey::owned("acme", "welcome");
+        let orphan = TemplateKey::shared("orphan");
+
+        repository
+            .save(&template(&shared, None, "Welcome"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&shared, Some("pt-BR"), "Bem-vindo"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&shared, Some("es"), "Bienvenido"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&owned, None, "Welcome to Acme"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&orphan, Some("es"), "Huérfano"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            repository.list_with_locales().await.unwrap(),
+            [
+                (shared, vec!["es".to_string(), "pt-BR".to_string()]),
+                (owned, Vec::new()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn deletes_a_translation_or_the_whole_template() {
+        let repository = repository().await;
+        let key = TemplateKey::shared("welcome");
+
+        repository
+            .save(&template(&key, None, "Welcome"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&key, Some("pt-BR"), "Bem-vindo"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&key, Some("es"), "Bienvenido"))
+            .await
+            .unwrap();
+
+        repository.delete(&key, Some("pt-BR")).await.unwrap();
+        assert_eq!(repository.list_locales(&key).await.unwrap(), ["es"]);
+
+        repository.delete(&key, None).await.unwrap();
+        assert!(repository.list_with_locales().await.unwrap().is_empty());
+
+        assert!(matches!(
+            repository.delete(&key, None).await,
+            Err(TemplateError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn the_revision_changes_on_saves_and_deletes() {
+        let repository = repository().await;
+        let key = TemplateKey::shared("welcome");
+
+        let empty = repository.revision().await.unwrap();
+
+        repository
+            .save(&template(&key, None, "Welcome"))
+            .await
+            .unwrap();
+        repository
+            .save(&template(&key, Some("es"), "Bienvenido"))
+            .await
+            .unwrap();
+        let saved = repository.revision().await.unwrap();
+        assert_ne!(saved, empty);
+
+        repository
+            .save_partial(
+                PartialKind::Partial,
+                &TemplatePartial {
+                    name: "footer".to_string(),
+                    source: "<footer></footer>".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        let with_partial = repository.revision().await.unwrap();
+        assert_ne!(with_partial, saved);
+
+        repository.delete(&key, Some("es")).await.unwrap();
+        assert_ne!(repository.revision().await.unwrap(), with_partial);
+    }
+}
@@ -1,18 +1,142 @@
+use std::fmt;
+use std::path::Path;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use super::{format::TemplateFormat, processing::HtmlProcessing, variables::TemplateVariable};
+
+// Template IDs become file names, so separators and dots are not allowed
+pub static TEMPLATE_ID: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9][A-Za-z0-9_-]{0,127}$").unwrap());
+
 #[derive(Error, Debug)]
 pub enum TemplateError {
     #[error("Template not found: {0}")]
     NotFound(String),
 
+    #[error("Invalid template ID: {0}")]
+    InvalidId(String),
+
+    #[error("Invalid locale: {0}")]
+    InvalidLocale(String),
+
+    #[error("Invalid template {id} at {location}: {message}")]
+    InvalidTemplate {
+        id: String,
+        location: String,
+        message: String,
+    },
+
+    #[error("{0} templates failed validation")]
+    ValidationFailed(usize),
+
+    #[error("{0} partials or layouts failed to compile")]
+    BrokenPartials(usize),
+
+    #[error("Missing variable {name} in template {template_id}")]
+    MissingVariable { name: String, template_id: String },
+
     #[error("Failed to render template: {0}")]
     RenderError(String),
+
+    #[error("Template store error: {0}")]
+    StoreError(String),
+}
+
+impl TemplateError {
+    /// A template file that is not valid JSON, located by file and line
+    pub fn invalid_json(id: &str, path: &Path, err: serde_json::Error) -> Self {
+        TemplateError::InvalidTemplate {
+            id: id.to_string(),
+            location: format!("{}:{}:{}", path.display(), err.line(), err.column()),
+            message: err.to_string(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmailTemplate {
     pub id: String,
+    /// Organization that manages the template through the API. Templates
+    /// without one are shared by every organization.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<String>,
+    /// Language the template is written in, `None` for the default template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
     pub subject: String,
     pub body: String,
+    #[serde(default)]
+    pub format: TemplateFormat,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_body: Option<String>,
+    /// Metadata the template expects, checked when a notification is created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<TemplateVariable>,
+    /// Fail on missing variables instead of rendering them empty. Defaults
+    /// to the service-wide setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+    /// Layout from the `layouts` directory that wraps the body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// Hidden text that clients show as the inbox preview
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preheader: Option<String>,
+    #[serde(default)]
+    pub processing: HtmlProcessing,
+}
+
+impl EmailTemplate {
+    pub fn key(&self) -> TemplateKey {
+        TemplateKey {
+            organization_id: self.organization_id.clone(),
+            id: self.id.clone(),
+        }
+    }
+}
+
+/// Identifies a stored template: its ID among the templates of the
+/// organization managing it, or among the shared templates. Organizations
+/// can use the same IDs without seeing each other's templates.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TemplateKey {
+    pub organization_id: Option<String>,
+    pub id: String,
+}
+
+impl TemplateKey {
+    pub fn shared(id: &str) -> Self {
+        Self {
+            organization_id: None,
+            id: id.to_string(),
+        }
+    }
+
+    pub fn owned(organization_id: &str, id: &str) -> Self {
+        Self {
+            organization_id: Some(organization_id.to_string()),
+            id: id.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TemplateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.organization_id {
+            Some(organization_id) => write!(f, "{}/{}", organization_id, self.id),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+pub fn validate_template_id(id: &str) -> Result<(), TemplateError> {
+    if TEMPLATE_ID.is_match(id) {
+        Ok(())
+    } else {
+        Err(TemplateError::InvalidId(id.to_string()))
+    }
 }
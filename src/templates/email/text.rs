@@ -0,0 +1,182 @@
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+static HIDDEN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?is)<!--.*?-->|<(head|style|script|title)\b[^>]*>.*?</(head|style|script|title)\s*>",
+    )
+    .unwrap()
+});
+
+static WHITESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+").unwrap());
+
+static LINK: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?is)<a\b[^>]*?\bhref\s*=\s*(?:"([^"]*)"|'([^']*)')[^>]*>(.*?)</a\s*>"#).unwrap()
+});
+
+static LINE_BREAK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<br\b[^>]*>").unwrap());
+
+static RULE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<hr\b[^>]*>").unwrap());
+
+static LIST_ITEM: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<li\b[^>]*>").unwrap());
+
+// Only the end of a row breaks the line, so rows don't end up a blank line
+// apart
+static ROW: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)</tr\s*>").unwrap());
+
+static CELL: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)</t[dh]\s*>").unwrap());
+
+static BLOCK: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)</?(p|div|h[1-6]|table|ul|ol|blockquote|section|article|header|footer|body)\b[^>]*>",
+    )
+    .unwrap()
+});
+
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+static ENTITY: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"&(#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z]+);").unwrap());
+
+static BLANK_LINES: Lazy<Regex> = Lazy::new(|| Regex::new(r"\n{3,}").unwrap());
+
+/// Converts rendered HTML into a readable plain-text alternative. Tags are
+/// stripped, blocks become paragraphs and links keep their URL next to the
+/// label.
+pub fn html_to_text(html: &str) -> String {
+    let text = HIDDEN.replace_all(html, "");
+
+    // Source formatting is not significant in HTML, only the tags are
+    let text = WHITESPACE.replace_all(&text, " ");
+
+    let text = LINK.replace_all(&text, |captures: &Captures| {
+        let href = captures
+            .get(1)
+            .or_else(|| captures.get(2))
+            .map_or("", |href| href.as_str())
+            .trim();
+        let label = TAG.replace_all(&captures[3], "");
+        let label = label.trim();
+        let url = href.strip_prefix("mailto:").unwrap_or(href);
+
+        if label.is_empty() {
+            url.to_string()
+        } else if url.is_empty() || url.starts_with('#') || url == label {
+            label.to_string()
+        } else {
+            format!("{} ({})", label, url)
+        }
+    });
+
+    let text = LINE_BREAK.replace_all(&text, "\n");
+    let text = RULE.replace_all(&text, "\n\n---\n\n");
+    let text = LIST_ITEM.replace_all(&text, "\n- ");
+    let text = ROW.replace_all(&text, "\n");
+    let text = CELL.replace_all(&text, " ");
+    let text = BLOCK.replace_all(&text, "\n\n");
+    let text = TAG.replace_all(&text, "");
+    let text = ENTITY.replace_all(&text, |captures: &Captures| {
+        decode_entity(&captures[1]).unwrap_or_else(|| captures[0].to_string())
+    });
+
+    let text = text
+        .lines()
+        .map(|line| line.trim())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    BLANK_LINES.replace_all(&text, "\n\n").trim().to_string()
+}
+
+fn decode_entity(entity: &str) -> Option<String> {
+    if let Some(code) = entity.strip_prefix('#') {
+        let code = match code.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => code.parse().ok()?,
+        };
+
+        return char::from_u32(code).map(String::from);
+    }
+
+    let decoded = match entity {
+        "amp" => "&",
+        "lt" => "<",
+        "gt" => ">",
+        "quot" => "\"",
+        "apos" => "'",
+        "nbsp" => " ",
+        "copy" => "©",
+        "reg" => "®",
+        "trade" => "™",
+        "hellip" => "…",
+        "mdash" => "—",
+        "ndash" => "–",
+        "lsquo" => "‘",
+        "rsquo" => "’",
+        "ldquo" => "“",
+        "rdquo" => "”",
+        "euro" => "€",
+        "pound" => "£",
+        _ => return None,
+    };
+
+    Some(decoded.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_become_paragraphs_and_lists_become_items() {
+        let html = "<html><head><title>Welcome</title><style>p { color: red; }</style></head>
+            <body>
+                <h1>Welcome</h1>
+                <p>Hi <b>Ana</b>,<br>thanks for
+                    signing up.</p>
+                <ul><li>One</li><li>Two</li></ul>
+                <!-- tracking -->
+            </body></html>";
+
+        assert_eq!(
+            html_to_text(html),
+            "Welcome\n\nHi Ana,\nthanks for signing up.\n\n- One\n- Two"
+        );
+    }
+
+    #[test]
+    fn links_keep_their_url() {
+        assert_eq!(
+            html_to_text(r#"<a href="https://example.com/reset">Reset <b>password</b></a>"#),
+            "Reset password (https://example.com/reset)"
+        );
+        assert_eq!(
+            html_to_text("<a href='mailto:help@example.com'>help@example.com</a>"),
+            "help@example.com"
+        );
+        assert_eq!(
+            html_to_text(r##"<a href="#top">Back to top</a>"##),
+            "Back to top"
+        );
+        assert_eq!(
+            html_to_text(r#"<a href="https://example.com"></a>"#),
+            "https://example.com"
+        );
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(
+            html_to_text("<p>Fish &amp; chips &#8212; &#x20AC;5 &copy; &unknown;</p>"),
+            "Fish & chips — €5 © &unknown;"
+        );
+    }
+
+    #[test]
+    fn tables_become_rows() {
+        assert_eq!(
+            html_to_text("<table><tr><td>Item</td><td>Price</td></tr><tr><td>Book</td><td>$10</td></tr></table>"),
+            "Item Price\nBook $10"
+        );
+    }
+}
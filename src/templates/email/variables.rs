@@ -0,0 +1,211 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// JSON type expected for a template variable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VariableType {
+    #[default]
+    Any,
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl VariableType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VariableType::Any => "any",
+            VariableType::String => "string",
+            VariableType::Number => "number",
+            VariableType::Integer => "integer",
+            VariableType::Boolean => "boolean",
+            VariableType::Array => "array",
+            VariableType::Object => "object",
+        }
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            VariableType::Any => true,
+            VariableType::String => value.is_string(),
+            VariableType::Number => value.is_number(),
+            VariableType::Integer => value.is_i64() || value.is_u64(),
+            VariableType::Boolean => value.is_boolean(),
+            VariableType::Array => value.is_array(),
+            VariableType::Object => value.is_object(),
+        }
+    }
+}
+
+/// A metadata field used by a template, named by its path in the metadata,
+/// like `user.name`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateVariable {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: VariableType,
+    #[serde(default = "required_by_default")]
+    pub required: bool,
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariableError {
+    Missing(String),
+    Mistyped {
+        name: String,
+        expected: VariableType,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for VariableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VariableError::Missing(name) => write!(f, "{} is required", name),
+            VariableError::Mistyped {
+                name,
+                expected,
+                found,
+            } => write!(f, "{} must be {}, got {}", name, expected.as_str(), found),
+        }
+    }
+}
+
+/// Checks the metadata of a notification against the variables its template
+/// declares. A `null` value counts as missing.
+pub fn validate_metadata(variables: &[TemplateVariable], metadata: &Value) -> Vec<VariableError> {
+    variables
+        .iter()
+        .filter_map(|variable| match lookup(metadata, &variable.name) {
+            None | Some(Value::Null) if variable.required => {
+                Some(VariableError::Missing(variable.name.clone()))
+            }
+            None | Some(Value::Null) => None,
+            Some(value) if !variable.kind.matches(value) => Some(VariableError::Mistyped {
+                name: variable.name.clone(),
+                expected: variable.kind,
+                found: json_type(value),
+            }),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+fn lookup<'a>(metadata: &'a Value, name: &str) -> Option<&'a Value> {
+    name.split('.')
+        .try_fold(metadata, |value, segment| value.get(segment))
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn variable(name: &str, kind: VariableType, required: bool) -> TemplateVariable {
+        TemplateVariable {
+            name: name.to_string(),
+            kind,
+            required,
+        }
+    }
+
+    #[test]
+    fn missing_and_null_required_variables_are_reported() {
+        let variables = [
+            variable("username", VariableType::String, true),
+            variable("code", VariableType::String, true),
+        ];
+
+        assert_eq!(
+            validate_metadata(&variables, &json!({ "code": null })),
+            vec![
+                VariableError::Missing("username".to_string()),
+                VariableError::Missing("code".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn absent_optional_variables_are_accepted() {
+        let variables = [variable("nickname", VariableType::String, false)];
+
+        assert!(validate_metadata(&variables, &json!({})).is_empty());
+        assert!(validate_metadata(&variables, &json!({ "nickname": null })).is_empty());
+    }
+
+    #[test]
+    fn mistyped_variables_are_reported() {
+        let variables = [variable("total", VariableType::Number, true)];
+
+        assert_eq!(
+            validate_metadata(&variables, &json!({ "total": "10" })),
+            vec![VariableError::Mistyped {
+                name: "total".to_string(),
+                expected: VariableType::Number,
+                found: "string",
+            }]
+        );
+    }
+
+    #[test]
+    fn integers_are_numbers_but_not_the_reverse() {
+        let number = [variable("total", VariableType::Number, true)];
+        let integer = [variable("count", VariableType::Integer, true)];
+
+        assert!(validate_metadata(&number, &json!({ "total": 10 })).is_empty());
+        assert!(validate_metadata(&integer, &json!({ "count": 10 })).is_empty());
+        assert_eq!(
+            validate_metadata(&integer, &json!({ "count": 1.5 })),
+            vec![VariableError::Mistyped {
+                name: "count".to_string(),
+                expected: VariableType::Integer,
+                found: "number",
+            }]
+        );
+    }
+
+    #[test]
+    fn dotted_names_look_up_nested_fields() {
+        let variables = [variable("user.name", VariableType::String, true)];
+
+        assert!(validate_metadata(&variables, &json!({ "user": { "name": "Ana" } })).is_empty());
+        assert_eq!(
+            validate_metadata(
+                &variables,
+                &json!({ "user": { "email": "ana@example.com" } })
+            ),
+            vec![VariableError::Missing("user.name".to_string())]
+        );
+        assert_eq!(
+            validate_metadata(&variables, &json!({ "user": { "name": 1 } })),
+            vec![VariableError::Mistyped {
+                name: "user.name".to_string(),
+                expected: VariableType::String,
+                found: "integer",
+            }]
+        );
+    }
+}
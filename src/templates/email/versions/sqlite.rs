@@ -0,0 +1,459 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{Executor, FromRow, Sqlite, SqlitePool};
+
+use super::store::{TemplateSnapshot, TemplateVersion, TemplateVersionStore, VersionError};
+use crate::templates::email::template::TemplateKey;
+
+// Versions of shared templates have an empty organization ID
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS email_template_versions (
+    organization_id TEXT NOT NULL DEFAULT '',
+    template_id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (organization_id, template_id, version)
+);
+
+CREATE TABLE IF NOT EXISTS email_template_published (
+    organization_id TEXT NOT NULL DEFAULT '',
+    template_id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    published_at TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (organization_id, template_id)
+);
+"#;
+
+const SHARED: &str = "";
+
+const SELECT_VERSIONS: &str =
+    "SELECT v.version, v.content, v.created_at, p.version IS NOT NULL AS published
+     FROM email_template_versions v
+     LEFT JOIN email_template_published p
+         ON p.organization_id = v.organization_id
+         AND p.template_id = v.template_id
+         AND p.version = v.version";
+
+#[derive(FromRow)]
+struct VersionRow {
+    version: i64,
+    content: String,
+    created_at: String,
+    published: bool,
+}
+
+/// Keeps every version of the email templates, so notifications can render
+/// the exact version they were created against
+pub struct SqliteTemplateVersionStore {
+    pool: SqlitePool,
+}
+
+impl SqliteTemplateVersionStore {
+    pub async fn new(pool: SqlitePool) -> Result<Self, VersionError> {
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .map_err(store_error)?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl TemplateVersionStore for SqliteTemplateVersionStore {
+    async fn record(
+        &self,
+        key: &TemplateKey,
+        snapshot: &TemplateSnapshot,
+    ) -> Result<TemplateVersion, VersionError> {
+        let content = serde_json::to_string(&snapshot.normalized())
+            .map_err(|err| VersionError::StoreError(err.to_string()))?;
+
+        // Takes the write lock up front, so concurrent records can't both
+        // read the same latest version and number theirs alike
+        let mut transaction = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(store_error)?;
+
+        let latest: Option<(i64, String)> = sqlx::query_as(
+            "SELECT version, content FROM email_template_versions
+             WHERE organization_id = ? AND template_id = ?
+             ORDER BY version DESC
+             LIMIT 1",
+        )
+        .bind(owner(key))
+        .bind(&key.id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(store_error)?;
+
+        let version = match latest {
+            Some((version, latest_content)) if latest_content == content => version,
+            latest => {
+                let version = latest.map_or(1, |(version, _)| version + 1);
+
+                sqlx::query(
+                    "INSERT INTO email_template_versions
+                         (organization_id, template_id, version, content, created_at)
+                     VALUES (?, ?, ?, ?, ?)",
+                )
+                .bind(owner(key))
+                .bind(&key.id)
+                .bind(version)
+                .bind(&content)
+                .bind(Utc::now().to_rfc3339())
+                .execute(&mut *transaction)
+                .await
+                .map_err(store_error)?;
+
+                version
+            }
+        };
+
+        set_published(&mut *transaction, key, version).await?;
+
+        let published = sqlx::query_as::<_, VersionRow>(&format!(
+            "{} WHERE v.organization_id = ? AND v.template_id = ? AND v.version = ?",
+            SELECT_VERSIONS
+        ))
+        .bind(owner(key))
+        .bind(&key.id)
+        .bind(version)
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(store_error)?;
+
+        transaction.commit().await.map_err(store_error)?;
+
+        into_version(key, published)
+    }
+
+    async fn published(&self, key: &TemplateKey) -> Result<Option<TemplateVersion>, VersionError> {
+        let row = sqlx::query_as::<_, VersionRow>(&format!(
+            "{} WHERE v.organization_id = ? AND v.template_id = ? AND p.version IS NOT NULL",
+            SELECT_VERSIONS
+        ))
+        .bind(owner(key))
+        .bind(&key.id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        row.map(|row| into_version(key, row)).transpose()
+    }
+
+    async fn latest(&self, key: &TemplateKey) -> Result<Option<TemplateVersion>, VersionError> {
+        let row = sqlx::query_as::<_, VersionRow>(&format!(
+            "{} WHERE v.organization_id = ? AND v.template_id = ? ORDER BY v.version DESC LIMIT 1",
+            SELECT_VERSIONS
+        ))
+        .bind(owner(key))
+        .bind(&key.id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        row.map(|row| into_version(key, row)).transpose()
+    }
+
+    async fn list(&self, key: &TemplateKey) -> Result<Vec<TemplateVersion>, VersionError> {
+        let rows = sqlx::query_as::<_, VersionRow>(&format!(
+            "{} WHERE v.organization_id = ? AND v.template_id = ? ORDER BY v.version DESC",
+            SELECT_VERSIONS
+        ))
+        .bind(owner(key))
+        .bind(&key.id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        rows.into_iter().map(|row| into_version(key, row)).collect()
+    }
+
+    async fn find(&self, key: &TemplateKey, version: i64) -> Result<TemplateVersion, VersionError> {
+        let row = sqlx::query_as::<_, VersionRow>(&format!(
+            "{} WHERE v.organization_id = ? AND v.template_id = ? AND v.version = ?",
+            SELECT_VERSIONS
+        ))
+        .bind(owner(key))
+        .bind(&key.id)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(store_error)?
+        .ok_or_else(|| VersionError::NotFound {
+            template_id: key.to_string(),
+            version,
+        })?;
+
+        into_version(key, row)
+    }
+
+    async fn publish(
+        &self,
+        key: &TemplateKey,
+        version: i64,
+    ) -> Result<TemplateVersion, VersionError> {
+        // Fails with NotFound before moving the pointer to a missing version
+        self.find(key, version).await?;
+
+        set_published(&self.pool, key, version).await?;
+
+        self.find(key, version).await
+    }
+
+    async fn previous(&self, key: &TemplateKey) -> Result<TemplateVersion, VersionError> {
+        let previous: Option<i64> = sqlx::query_scalar(
+            "SELECT MAX(v.version)
+             FROM email_template_versions v
+             JOIN email_template_published p
+                 ON p.organization_id = v.organization_id AND p.template_id = v.template_id
+             WHERE v.organization_id = ? AND v.template_id = ? AND v.version < p.version",
+        )
+        .bind(owner(key))
+        .bind(&key.id)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(store_error)?;
+
+        let previous = previous.ok_or_else(|| VersionError::NothingToRollBack(key.to_string()))?;
+
+        self.find(key, previous).await
+    }
+
+    async fn revision(&self) -> Result<String, VersionError> {
+        sqlx::query_scalar("SELECT COALESCE(MAX(published_at), '') FROM email_template_published")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(store_error)
+    }
+}
+
+async fn set_published<'e>(
+    executor: impl Executor<'e, Database = Sqlite>,
+    key: &TemplateKey,
+    version: i64,
+) -> Result<(), VersionError> {
+    sqlx::query(
+        "INSERT INTO email_template_published
+             (organization_id, template_id, version, published_at)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT (organization_id, template_id) DO UPDATE
+         SET version = excluded.version, published_at = excluded.published_at",
+    )
+    .bind(owner(key))
+    .bind(&key.id)
+    .bind(version)
+    .bind(Utc::now().to_rfc3339())
+    .execute(executor)
+    .await
+    .map_err(store_error)?;
+
+    Ok(())
+}
+
+fn into_version(key: &TemplateKey, row: VersionRow) -> Result<TemplateVersion, VersionError> {
+    let snapshot: TemplateSnapshot = serde_json::from_str(&row.content)
+        .map_err(|err| VersionError::StoreError(err.to_string()))?;
+
+    Ok(TemplateVersion {
+        key: key.clone(),
+        version: row.version,
+        snapshot,
+        created_at: row.created_at,
+        published: row.published,
+    })
+}
+
+/// The organization column of a key
+fn owner(key: &TemplateKey) -> &str {
+    key.organization_id.as_deref().unwrap_or(SHARED)
+}
+
+fn store_error(err: sqlx::Error) -> VersionError {
+    VersionError::StoreError(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::infra::sqlite;
+    use crate::templates::email::template::EmailTemplate;
+
+    async fn store() -> SqliteTemplateVersionStore {
+        SqliteTemplateVersionStore::new(sqlite::memory().await)
+            .await
+            .unwrap()
+    }
+
+    fn snapshot(key: &TemplateKey, subject: &str) -> TemplateSnapshot {
+        let mut template: EmailTemplate = serde_json::from_value(json!({
+            "id": key.id,
+            "subject": subject,
+            "body": "<p>Hello</p>",
+        }))
+        .unwrap();
+        template.organization_id = key.organization_id.clone();
+
+        TemplateSnapshot {
+            templates: vec![template],
+            partials: Vec::new(),
+            layouts: Vec::new(),
+        }
+    }
+
+    fn subject(version: &TemplateVersion) -> &str {
+        &version.snapshot.templates[0].subject
+    }
+
+    #[tokio::test]
+    async fn records_numbered_versions_and_publishes_the_latest() {
+        let store = store().await;
+        let key = TemplateKey::shared("welcome");
+
+        let first = store
+            .record(&key, &snapshot(&key, "Welcome"))
+            .await
+            .unwrap();
+        let second = store.record(&key, &snapshot(&key, "Hi")).await.unwrap();
+
+        assert_eq!((first.version, second.version), (1, 2));
+        assert!(second.published);
+
+        let published = store.published(&key).await.unwrap().unwrap();
+        assert_eq!(published.version, 2);
+        assert_eq!(subject(&published), "Hi");
+
+        let versions = store.list(&key).await.unwrap();
+        assert_eq!(
+            versions
+                .iter()
+                .map(|version| version.version)
+                .collect::<Vec<_>>(),
+            [2, 1]
+        );
+        assert!(!versions[1].published);
+    }
+
+    #[tokio::test]
+    async fn recording_an_identical_snapshot_reuses_the_latest_version() {
+        let store = store().await;
+        let key = TemplateKey::shared("welcome");
+
+        store
+            .record(&key, &snapshot(&key, "Welcome"))
+            .await
+            .unwrap();
+        let again = store
+            .record(&key, &snapshot(&key, "Welcome"))
+            .await
+            .unwrap();
+
+        assert_eq!(again.version, 1);
+        assert_eq!(store.list(&key).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_to_the_version_before_the_published_one() {
+        let store = store().await;
+        let key = TemplateKey::shared("welcome");
+
+        for subject in ["One", "Two", "Three"] {
+            store.record(&key, &snapshot(&key, subject)).await.unwrap();
+        }
+
+        let previous = store.previous(&key).await.unwrap();
+        assert_eq!(previous.version, 2);
+
+        store.publish(&key, previous.version).await.unwrap();
+        assert_eq!(store.previous(&key).await.unwrap().version, 1);
+        assert_eq!(store.latest(&key).await.unwrap().unwrap().version, 3);
+
+        store.publish(&key, 1).await.unwrap();
+        assert!(matches!(
+            store.previous(&key).await,
+            Err(VersionError::NothingToRollBack(_))
+        ));
+        assert!(matches!(
+            store.publish(&key, 4).await,
+            Err(VersionError::NotFound { version: 4, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn versions_of_owned_and_shared_templates_are_kept_apart() {
+        let store = store().await;
+        let shared = TemplateKey::shared("welcome");
+        let acme = TemplateKey::owned("acme", "welcome");
+        let globex = TemplateKey::owned("globex", "welcome");
+
+        store
+            .record(&shared, &snapshot(&shared, "Welcome"))
+            .await
+            .unwrap();
+        store
+            .record(&shared, &snapshot(&shared, "Hi"))
+            .await
+            .unwrap();
+        let owned = store
+            .record(&acme, &snapshot(&acme, "Welcome to Acme"))
+            .await
+            .unwrap();
+
+        assert_eq!(owned.version, 1);
+        assert_eq!(store.list(&acme).await.unwrap().len(), 1);
+        assert_eq!(
+            subject(&store.find(&acme, 1).await.unwrap()),
+            "Welcome to Acme"
+        );
+        assert_eq!(subject(&store.find(&shared, 1).await.unwrap()), "Welcome");
+        assert!(store.published(&globex).await.unwrap().is_none());
+        assert!(matches!(
+            store.find(&acme, 2).await,
+            Err(VersionError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_records_get_distinct_versions() {
+        let directory = tempfile::tempdir().unwrap();
+        let url = format!(
+            "sqlite://{}",
+            directory.path().join("versions.db").display()
+        );
+        let store = Arc::new(
+            SqliteTemplateVersionStore::new(sqlite::connect(&url).await.unwrap())
+                .await
+                .unwrap(),
+        );
+        let key = TemplateKey::shared("welcome");
+
+        let records = (0..8).map(|index| {
+            let store = Arc::clone(&store);
+            let key = key.clone();
+
+            tokio::spawn(async move {
+                store
+                    .record(&key, &snapshot(&key, &format!("Welcome {}", index)))
+                    .await
+                    .unwrap()
+                    .version
+            })
+        });
+
+        let mut versions = Vec::new();
+        for record in records {
+            versions.push(record.await.unwrap());
+        }
+        versions.sort();
+
+        assert_eq!(versions, (1..=8).collect::<Vec<_>>());
+    }
+}
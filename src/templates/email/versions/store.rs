@@ -0,0 +1,110 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::templates::email::{
+    repository::TemplatePartial,
+    template::{EmailTemplate, TemplateError, TemplateKey},
+};
+
+#[derive(Error, Debug)]
+pub enum VersionError {
+    #[error("Template version not found: {template_id} v{version}")]
+    NotFound { template_id: String, version: i64 },
+
+    #[error("No earlier version of template {0} to roll back to")]
+    NothingToRollBack(String),
+
+    #[error("Template version store error: {0}")]
+    StoreError(String),
+
+    #[error(transparent)]
+    TemplateError(#[from] TemplateError),
+}
+
+impl From<VersionError> for TemplateError {
+    fn from(err: VersionError) -> Self {
+        match err {
+            VersionError::NotFound {
+                template_id,
+                version,
+            } => TemplateError::NotFound(format!("{} v{}", template_id, version)),
+            VersionError::TemplateError(err) => err,
+            other => TemplateError::StoreError(other.to_string()),
+        }
+    }
+}
+
+/// A template and its translations, along with the partials and layouts
+/// they use
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateSnapshot {
+    /// The default template first, then its translations
+    pub templates: Vec<EmailTemplate>,
+    pub partials: Vec<TemplatePartial>,
+    pub layouts: Vec<TemplatePartial>,
+}
+
+impl TemplateSnapshot {
+    /// The snapshot with its templates ordered by locale, so snapshots of the
+    /// same templates serialize the same
+    pub fn normalized(&self) -> Self {
+        let mut snapshot = self.clone();
+        snapshot.templates.sort_by(|a, b| a.locale.cmp(&b.locale));
+
+        snapshot
+    }
+
+    /// Whether both snapshots hold the same templates, partials and layouts
+    pub fn matches(&self, other: &TemplateSnapshot) -> bool {
+        serde_json::to_value(self.normalized()).ok()
+            == serde_json::to_value(other.normalized()).ok()
+    }
+}
+
+/// An immutable snapshot of a template, which renders the same however the
+/// shared partials and layouts change afterwards
+#[derive(Debug, Clone)]
+pub struct TemplateVersion {
+    pub key: TemplateKey,
+    pub version: i64,
+    pub snapshot: TemplateSnapshot,
+    pub created_at: String,
+    pub published: bool,
+}
+
+#[async_trait]
+pub trait TemplateVersionStore: Send + Sync {
+    /// Records the snapshot as a new version, unless it matches the latest
+    /// one, and publishes it
+    async fn record(
+        &self,
+        key: &TemplateKey,
+        snapshot: &TemplateSnapshot,
+    ) -> Result<TemplateVersion, VersionError>;
+
+    /// The version new notifications render, if the template has any
+    async fn published(&self, key: &TemplateKey) -> Result<Option<TemplateVersion>, VersionError>;
+
+    /// The most recent version of a template, published or not
+    async fn latest(&self, key: &TemplateKey) -> Result<Option<TemplateVersion>, VersionError>;
+
+    /// Every version of a template, newest first
+    async fn list(&self, key: &TemplateKey) -> Result<Vec<TemplateVersion>, VersionError>;
+
+    async fn find(&self, key: &TemplateKey, version: i64) -> Result<TemplateVersion, VersionError>;
+
+    /// Makes the version the one new notifications render
+    async fn publish(
+        &self,
+        key: &TemplateKey,
+        version: i64,
+    ) -> Result<TemplateVersion, VersionError>;
+
+    /// The version before the published one
+    async fn previous(&self, key: &TemplateKey) -> Result<TemplateVersion, VersionError>;
+
+    /// Changes whenever a version of any template is published, so other
+    /// instances sharing the store can tell when to reload
+    async fn revision(&self) -> Result<String, VersionError>;
+}
@@ -0,0 +1,39 @@
+use handlebars::Handlebars;
+use serde_json::Value;
+
+use super::template::SmsTemplate;
+use crate::templates::email::template::TemplateError;
+
+pub struct SmsTemplateEngine {
+    handlebars: Handlebars<'static>,
+}
+
+impl Default for SmsTemplateEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SmsTemplateEngine {
+    pub fn new() -> Self {
+        let mut handlebars = Handlebars::new();
+
+        // SMS bodies are plain text, so values must not be HTML-escaped
+        handlebars.register_escape_fn(handlebars::no_escape);
+
+        Self { handlebars }
+    }
+
+    pub fn render(
+        &self,
+        template: &SmsTemplate,
+        metadata: &Value,
+    ) -> Result<String, TemplateError> {
+        let sms_content = self
+            .handlebars
+            .render_template(&template.body, metadata)
+            .map_err(|err| TemplateError::RenderError(err.to_string()))?;
+
+        Ok(sms_content)
+    }
+}
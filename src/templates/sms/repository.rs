@@ -0,0 +1,43 @@
+use async_trait::async_trait;
+use tokio::fs;
+
+use super::template::SmsTemplate;
+use crate::templates::email::{
+    repository::{io_error, resolve_template_path},
+    template::TemplateError,
+};
+
+use crate::tracing::error;
+
+#[async_trait]
+pub trait SmsTemplateRepository: Send + Sync {
+    async fn find_by_id(&self, id: &str) -> Result<SmsTemplate, TemplateError>;
+}
+
+pub struct FileSmsTemplateRepository {
+    templates_path: String,
+}
+
+impl FileSmsTemplateRepository {
+    pub fn new(templates_path: String) -> Self {
+        Self { templates_path }
+    }
+}
+
+#[async_trait]
+impl SmsTemplateRepository for FileSmsTemplateRepository {
+    async fn find_by_id(&self, id: &str) -> Result<SmsTemplate, TemplateError> {
+        let path = resolve_template_path(&self.templates_path, id, None).await?;
+
+        let content = fs::read_to_string(&path).await.map_err(|err| {
+            error!("Failed to read SMS template file: {:?}", err);
+
+            io_error(err, id)
+        })?;
+
+        let template: SmsTemplate = serde_json::from_str(&content)
+            .map_err(|err| TemplateError::invalid_json(id, &path, err))?;
+
+        Ok(template)
+    }
+}
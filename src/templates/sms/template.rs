@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SmsTemplate {
+    pub id: String,
+    pub body: String,
+}
@@ -1,97 +1,189 @@
 use std::sync::Arc;
 
 use amqprs::{BasicProperties, Deliver};
-use resend_rs::{types::CreateEmailBaseOptions, Resend};
 
 use crate::{
     domain::notification::{EmailNotification, Notification},
-    infra::consumer::ConsumerError,
-    templates::email::{
-        engine::EmailTemplateEngine,
-        repository::{EmailTemplateRepository, FileEmailTemplateRepository},
-    },
+    infra::consumer::{ConsumerError, Disposition},
+    providers::email::EmailSender,
+    templates::email::{cache::EmailTemplateCache, template::TemplateKey},
     tracing::{error, info},
 };
 
 pub struct EmailWorker {
-    repository: Arc<dyn EmailTemplateRepository>,
-    engine: Arc<EmailTemplateEngine>,
-    resend: Arc<Resend>,
-}
-
-impl Default for EmailWorker {
-    fn default() -> Self {
-        Self::new()
-    }
+    templates: Arc<EmailTemplateCache>,
+    sender: Arc<EmailSender>,
 }
 
 impl EmailWorker {
-    pub fn new() -> Self {
-        let repository = Arc::new(FileEmailTemplateRepository::new("templates".to_string()));
-        let engine = Arc::new(EmailTemplateEngine::new());
-        let resend = Arc::new(Resend::default());
-
-        Self {
-            repository,
-            engine,
-            resend,
-        }
+    pub fn new(templates: Arc<EmailTemplateCache>, sender: Arc<EmailSender>) -> Self {
+        Self { templates, sender }
     }
 
+    /// Delivers the notification and returns the provider's message ID
     pub async fn handle(
         &self,
         _deliver: Deliver,
         _properties: BasicProperties,
         content: Vec<u8>,
-    ) -> Result<(), Box<dyn std::error::Error + Send>> {
+    ) -> Result<String, ConsumerError> {
         info!("Consuming email notification");
 
         let json_content = String::from_utf8(content).map_err(|err| {
             error!("Failed to decode email notification: {:?}", err);
 
-            Box::new(ConsumerError::DecodeError) as Box<dyn std::error::Error + Send>
+            ConsumerError::DecodeError
         })?;
 
         info!("Json email notification: {:?}", &json_content);
 
         let notification = EmailNotification::from_json_string(&json_content).map_err(|err| {
             error!("Failed to parse email notification: {:?}", err);
-            Box::new(ConsumerError::ParseError) as Box<dyn std::error::Error + Send>
+            ConsumerError::ParseError
         })?;
 
         info!("Parsed email notification: {:?}", notification);
 
-        let template = self
-            .repository
-            .find_by_id(&notification.template_id)
+        let template = TemplateKey {
+            organization_id: notification.template_organization_id.clone(),
+            id: notification.template_id.clone(),
+        };
+
+        let rendered = self
+            .templates
+            .render(
+                &template,
+                notification.template_version,
+                notification.locale.as_deref(),
+                &notification.metadata,
+            )
             .await
             .map_err(|err| {
-                error!("Failed to find email template: {:?}", err);
-                Box::new(ConsumerError::ParseError) as Box<dyn std::error::Error + Send>
+                error!("Failed to render email template: {:?}", err);
+                ConsumerError::TemplateError(err)
             })?;
 
-        let rendered = self
-            .engine
-            .render(&template, &notification.metadata)
+        info!("Rendered email notification: {:?}", rendered);
+
+        let message_id = self
+            .sender
+            .send(&notification.recipient, &rendered)
+            .await
             .map_err(|err| {
-                error!("Failed to render email html: {:?}", err);
-                Box::new(ConsumerError::ParseError) as Box<dyn std::error::Error + Send>
+                error!("Failed to send email notification: {:?}", err);
+                resend_error(err)
             })?;
 
-        info!("Rendered email notification: {:?}", rendered);
+        info!(
+            "Email for notification {} sent with provider message id {}",
+            notification.id, message_id
+        );
+
+        Ok(message_id)
+    }
+}
 
-        let from = "Crab Notifications <onboarding@resend.dev>"; // Local development e-mail source
-        let to = [notification.recipient];
-        let subject = template.subject;
-        let email = CreateEmailBaseOptions::new(from, to, subject).with_html(&rendered);
+/// Network failures, rate limits and Resend-side errors may succeed when
+/// retried. An unparseable response is not retried since the email may have
+/// been sent already.
+fn resend_error(err: resend_rs::Error) -> ConsumerError {
+    let disposition = match &err {
+        resend_rs::Error::Http(_) | resend_rs::Error::RateLimit { .. } => Disposition::Transient,
+        resend_rs::Error::Resend(response)
+            if response.status_code == 429 || response.status_code >= 500 =>
+        {
+            Disposition::Transient
+        }
+        resend_rs::Error::Resend(_) | resend_rs::Error::Parse(_) => Disposition::Permanent,
+    };
 
-        let _email = self.resend.emails.send(email).await.map_err(|err| {
-            error!("Failed to send email notification: {:?}", err);
-            Box::new(ConsumerError::ParseError) as Box<dyn std::error::Error + Send>
-        })?;
+    ConsumerError::DeliveryError {
+        message: err.to_string(),
+        disposition,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use resend_rs::types::ErrorResponse;
 
-        info!("Email for notification {} sent", notification.id);
+    use super::*;
 
-        Ok(())
+    fn resend_response(status_code: u16, name: &str) -> resend_rs::Error {
+        resend_rs::Error::Resend(ErrorResponse {
+            status_code,
+            message: "rejected".to_string(),
+            name: name.to_string(),
+        })
+    }
+
+    /// A request to a server that accepts the connection but never answers
+    async fn timed_out_request() -> reqwest::Error {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap()
+            .get(url)
+            .send()
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn resend_errors_map_to_their_disposition() {
+        let timeout = timed_out_request().await;
+        assert!(timeout.is_timeout());
+
+        let cases = [
+            (
+                resend_response(429, "rate_limit_exceeded"),
+                Disposition::Transient,
+            ),
+            (
+                resend_rs::Error::RateLimit {
+                    ratelimit_limit: Some(2),
+                    ratelimit_remaining: Some(0),
+                    ratelimit_reset: Some(1),
+                },
+                Disposition::Transient,
+            ),
+            (
+                resend_response(500, "internal_server_error"),
+                Disposition::Transient,
+            ),
+            (
+                resend_response(503, "application_error"),
+                Disposition::Transient,
+            ),
+            (
+                resend_response(422, "validation_error"),
+                Disposition::Permanent,
+            ),
+            (
+                resend_response(403, "invalid_to_address"),
+                Disposition::Permanent,
+            ),
+            (resend_rs::Error::Http(timeout), Disposition::Transient),
+            (
+                resend_rs::Error::Parse("<html>".to_string()),
+                Disposition::Permanent,
+            ),
+        ];
+
+        for (err, disposition) in cases {
+            let message = err.to_string();
+
+            assert_eq!(resend_error(err).disposition(), disposition, "{}", message);
+        }
     }
 }
@@ -1 +1,16 @@
 pub mod email;
+pub mod push;
+pub mod sms;
+
+/// Workers ignore the delivery, but amqprs has no public constructor for it
+#[cfg(test)]
+fn test_deliver() -> amqprs::Deliver {
+    serde_json::from_value(serde_json::json!({
+        "consumer_tag": [0, ""],
+        "delivery_tag": 1,
+        "redelivered": false,
+        "exchange": [0, ""],
+        "routing_key": [0, ""],
+    }))
+    .unwrap()
+}
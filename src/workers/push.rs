@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use amqprs::{BasicProperties, Deliver};
+
+use crate::{
+    domain::notification::{Notification, PushNotification},
+    infra::consumer::ConsumerError,
+    providers::push::{PushMessage, PushProvider},
+    tracing::{error, info},
+};
+
+pub struct PushWorker {
+    provider: Arc<dyn PushProvider>,
+}
+
+impl PushWorker {
+    pub fn new(provider: Arc<dyn PushProvider>) -> Self {
+        Self { provider }
+    }
+
+    /// Delivers the notification and returns the provider's message ID
+    pub async fn handle(
+        &self,
+        _deliver: Deliver,
+        _properties: BasicProperties,
+        content: Vec<u8>,
+    ) -> Result<String, ConsumerError> {
+        info!("Consuming push notification");
+
+        let json_content = String::from_utf8(content).map_err(|err| {
+            error!("Failed to decode push notification: {:?}", err);
+
+            ConsumerError::DecodeError
+        })?;
+
+        let notification = PushNotification::from_json_string(&json_content).map_err(|err| {
+            error!("Failed to parse push notification: {:?}", err);
+            ConsumerError::ParseError
+        })?;
+
+        info!("Parsed push notification: {:?}", notification);
+
+        let message = PushMessage {
+            device_token: notification.device_token,
+            module: notification.module,
+            title: notification.title,
+            description: notification.description,
+        };
+
+        let message_id = self.provider.send(&message).await.map_err(|err| {
+            error!("Failed to send push notification: {:?}", err);
+            ConsumerError::from(err)
+        })?;
+
+        info!(
+            "Push for notification {} sent with provider message id {}",
+            notification.id, message_id
+        );
+
+        Ok(message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        infra::consumer::Disposition,
+        providers::push::{fake::FakePushProvider, PushProviderError},
+        workers::test_deliver,
+    };
+
+    fn notification() -> Vec<u8> {
+        PushNotification::new(
+            "device-token".to_string(),
+            Some("orders".to_string()),
+            "Order shipped".to_string(),
+            None,
+        )
+        .to_json_string()
+        .unwrap()
+        .into_bytes()
+    }
+
+    async fn handle(
+        provider: &Arc<FakePushProvider>,
+        content: Vec<u8>,
+    ) -> Result<String, ConsumerError> {
+        PushWorker::new(provider.clone())
+            .handle(test_deliver(), BasicProperties::default(), content)
+            .await
+    }
+
+    #[tokio::test]
+    async fn sends_the_notification() {
+        let provider = Arc::new(FakePushProvider::new());
+
+        handle(&provider, notification()).await.unwrap();
+
+        let sent = provider.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].device_token, "device-token");
+        assert_eq!(sent[0].module.as_deref(), Some("orders"));
+        assert_eq!(sent[0].title, "Order shipped");
+    }
+
+    #[tokio::test]
+    async fn provider_outages_are_transient() {
+        let provider = Arc::new(FakePushProvider::new());
+        provider.fail_next(PushProviderError::Rejected {
+            status: 429,
+            message: "Quota exceeded".to_string(),
+        });
+
+        let err = handle(&provider, notification()).await.unwrap_err();
+
+        assert_eq!(err.disposition(), Disposition::Transient);
+        assert!(provider.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn credential_and_rejection_failures_are_permanent() {
+        let provider = Arc::new(FakePushProvider::new());
+        provider.fail_next(PushProviderError::CredentialsError(
+            "invalid key".to_string(),
+        ));
+        provider.fail_next(PushProviderError::Rejected {
+            status: 404,
+            message: "Unregistered device".to_string(),
+        });
+        provider.fail_next(PushProviderError::ResponseParseError(
+            "missing name".to_string(),
+        ));
+
+        for _ in 0..3 {
+            let err = handle(&provider, notification()).await.unwrap_err();
+
+            assert_eq!(err.disposition(), Disposition::Permanent);
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_payloads_are_permanent() {
+        let provider = Arc::new(FakePushProvider::new());
+
+        let err = handle(&provider, b"not json".to_vec()).await.unwrap_err();
+
+        assert_eq!(err.disposition(), Disposition::Permanent);
+        assert!(provider.sent().is_empty());
+    }
+}
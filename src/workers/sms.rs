@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use amqprs::{BasicProperties, Deliver};
+
+use crate::{
+    domain::notification::{Notification, SMSNotification},
+    infra::consumer::ConsumerError,
+    providers::sms::{SmsMessage, SmsProvider},
+    templates::sms::{
+        engine::SmsTemplateEngine,
+        repository::{FileSmsTemplateRepository, SmsTemplateRepository},
+    },
+    tracing::{error, info},
+};
+
+pub struct SmsWorker {
+    repository: Arc<dyn SmsTemplateRepository>,
+    engine: Arc<SmsTemplateEngine>,
+    provider: Arc<dyn SmsProvider>,
+}
+
+impl SmsWorker {
+    pub fn new(provider: Arc<dyn SmsProvider>, templates_path: String) -> Self {
+        let repository = Arc::new(FileSmsTemplateRepository::new(templates_path));
+        let engine = Arc::new(SmsTemplateEngine::new());
+
+        Self {
+            repository,
+            engine,
+            provider,
+        }
+    }
+
+    /// Delivers the notification and returns the provider's message ID
+    pub async fn handle(
+        &self,
+        _deliver: Deliver,
+        _properties: BasicProperties,
+        content: Vec<u8>,
+    ) -> Result<String, ConsumerError> {
+        info!("Consuming SMS notification");
+
+        let json_content = String::from_utf8(content).map_err(|err| {
+            error!("Failed to decode SMS notification: {:?}", err);
+
+            ConsumerError::DecodeError
+        })?;
+
+        let notification = SMSNotification::from_json_string(&json_content).map_err(|err| {
+            error!("Failed to parse SMS notification: {:?}", err);
+            ConsumerError::ParseError
+        })?;
+
+        info!("Parsed SMS notification: {:?}", notification);
+
+        let template = self
+            .repository
+            .find_by_id(&notification.template_id)
+            .await
+            .map_err(|err| {
+                error!("Failed to find SMS template: {:?}", err);
+                ConsumerError::TemplateError(err)
+            })?;
+
+        let body = self
+            .engine
+            .render(&template, &notification.metadata)
+            .map_err(|err| {
+                error!("Failed to render SMS body: {:?}", err);
+                ConsumerError::TemplateError(err)
+            })?;
+
+        let message = SmsMessage {
+            to: notification.phone_number,
+            body,
+        };
+
+        let message_id = self.provider.send(&message).await.map_err(|err| {
+            error!("Failed to send SMS notification: {:?}", err);
+            ConsumerError::from(err)
+        })?;
+
+        info!(
+            "SMS for notification {} sent with provider message id {}",
+            notification.id, message_id
+        );
+
+        Ok(message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        infra::consumer::Disposition,
+        providers::sms::{fake::FakeSmsProvider, SmsProviderError},
+        workers::test_deliver,
+    };
+
+    fn notification(template_id: &str) -> Vec<u8> {
+        SMSNotification::new(
+            template_id.to_string(),
+            "+5511999999999".to_string(),
+            json!({ "username": "Ana", "code": "123456" }),
+        )
+        .to_json_string()
+        .unwrap()
+        .into_bytes()
+    }
+
+    async fn handle(
+        provider: &Arc<FakeSmsProvider>,
+        content: Vec<u8>,
+    ) -> Result<String, ConsumerError> {
+        SmsWorker::new(provider.clone(), "templates/sms".to_string())
+            .handle(test_deliver(), BasicProperties::default(), content)
+            .await
+    }
+
+    #[tokio::test]
+    async fn sends_the_rendered_template() {
+        let provider = Arc::new(FakeSmsProvider::new());
+
+        handle(&provider, notification("org1-verification-code"))
+            .await
+            .unwrap();
+
+        let sent = provider.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "+5511999999999");
+        assert_eq!(sent[0].body, "Hi Ana, your verification code is 123456.");
+    }
+
+    #[tokio::test]
+    async fn provider_outages_are_transient() {
+        let provider = Arc::new(FakeSmsProvider::new());
+        provider.fail_next(SmsProviderError::Rejected {
+            status: 503,
+            message: "Service unavailable".to_string(),
+        });
+        provider.fail_next(SmsProviderError::RequestError("timed out".to_string()));
+
+        for _ in 0..2 {
+            let err = handle(&provider, notification("org1-verification-code"))
+                .await
+                .unwrap_err();
+
+            assert_eq!(err.disposition(), Disposition::Transient);
+        }
+
+        assert!(provider.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_messages_are_permanent() {
+        let provider = Arc::new(FakeSmsProvider::new());
+        provider.fail_next(SmsProviderError::Rejected {
+            status: 400,
+            message: "Invalid phone number".to_string(),
+        });
+
+        let err = handle(&provider, notification("org1-verification-code"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.disposition(), Disposition::Permanent);
+    }
+
+    #[tokio::test]
+    async fn missing_templates_and_bad_payloads_are_permanent() {
+        let provider = Arc::new(FakeSmsProvider::new());
+
+        let missing = handle(&provider, notification("missing"))
+            .await
+            .unwrap_err();
+        let malformed = handle(&provider, b"{".to_vec()).await.unwrap_err();
+
+        assert_eq!(missing.disposition(), Disposition::Permanent);
+        assert_eq!(malformed.disposition(), Disposition::Permanent);
+        assert!(provider.sent().is_empty());
+    }
+}